@@ -0,0 +1,314 @@
+use std::{collections::BTreeMap, ops::Not};
+
+use crate::{
+    client::Client,
+    dependencies::FetchData,
+    unarchiver::{Archive, Entry, EntryKind},
+    upgrade_graph::UpgradeGraph,
+    utils,
+};
+
+/// Which SQL scripts of older versions go into the package
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CarriedScripts {
+    /// Upgrade scripts such as `foo--1.0--1.1.sql`
+    Upgrades,
+    /// Install scripts such as `foo--1.0.sql` as well
+    All,
+}
+
+impl CarriedScripts {
+    /// Whether a script named e.g. `foo--1.0--1.1.sql` of one of `extensions` is carried forward
+    fn carries(self, file_name: &str, extensions: &[&str]) -> bool {
+        extensions.iter().any(|extension| {
+            let script = file_name
+                .strip_prefix(extension)
+                .and_then(|rest| rest.strip_prefix("--"))
+                .and_then(|rest| rest.strip_suffix(".sql"));
+
+            match script {
+                Some(versions) => versions.contains("--") || self == Self::All,
+                None => false,
+            }
+        })
+    }
+}
+
+/// The versions of `older_versions` which the scripts in `graph` can't update to `target`,
+/// or, when install scripts are carried, can't install
+fn missing_versions<'v>(
+    graph: &UpgradeGraph,
+    older_versions: &[&'v str],
+    target: &str,
+    carried: CarriedScripts,
+) -> Vec<&'v str> {
+    older_versions
+        .iter()
+        .copied()
+        .filter(|version| {
+            graph.can_upgrade(version, target).not()
+                || (carried == CarriedScripts::All && graph.can_install(version).not())
+        })
+        .collect()
+}
+
+/// Download the archives of published versions older than the one being packaged into
+/// `data.history`, oldest first. Archives are fetched newest first, and only until the scripts
+/// gathered so far cover every older version. Versions without an archive for `pg_major` are
+/// skipped.
+pub async fn fetch_history(
+    client: &Client,
+    data: &mut FetchData,
+    pg_major: u16,
+    carried: CarriedScripts,
+) {
+    let archive_version = data.archive_version();
+    let FetchData {
+        extension,
+        archive,
+        versions,
+        history,
+        ..
+    } = data;
+
+    let mut older_versions: Vec<&str> = versions
+        .iter()
+        .map(|version| version.version.as_str())
+        .filter(|version| utils::compare_versions(version, &archive_version).is_lt())
+        .collect();
+    older_versions.sort_by(|left, right| utils::compare_versions(right, left));
+
+    let mut graph = UpgradeGraph::from_archive(archive, &extension.name);
+
+    for version in &older_versions {
+        if missing_versions(&graph, &older_versions, &archive_version, carried).is_empty() {
+            break;
+        }
+
+        let archive = client
+            .fetch_version_archive(&extension.name, version, pg_major)
+            .await;
+
+        match archive {
+            Ok(archive) => {
+                graph.add_archive(&archive, &extension.name);
+                history.push((version.to_string(), archive));
+            }
+            Err(err) => eprintln!(
+                "{}: not carrying the scripts of version {version} forward: {err}",
+                extension.name
+            ),
+        }
+    }
+
+    history.sort_by(|(left, _), (right, _)| utils::compare_versions(left, right));
+}
+
+/// Add the SQL scripts of older versions in `history` which `archive`, of `version`, lacks.
+/// Scripts whose contents differ between versions are reported, and the newest copy is kept.
+/// Returns how many scripts were added.
+pub fn merge_scripts(
+    extension_name: &str,
+    version: &str,
+    archive: &mut Archive,
+    history: &[(String, Archive)],
+    carried: CarriedScripts,
+) -> usize {
+    // The extensions the current version ships, from their primary control files
+    let extensions: Vec<&str> = archive
+        .all_entries()
+        .iter()
+        .filter(|entry| entry.kind == EntryKind::Regular)
+        .filter_map(|entry| {
+            let file_name = entry.path.file_name()?.to_str()?;
+            let extension = file_name.strip_suffix(".control")?;
+
+            extension.contains("--").not().then_some(extension)
+        })
+        .collect();
+
+    let is_carried = |entry: &Entry| {
+        entry.kind == EntryKind::Regular
+            && entry
+                .path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|file_name| carried.carries(file_name, &extensions))
+    };
+
+    // Every script by file name, along with the version whose copy is kept
+    let mut scripts: BTreeMap<&str, (&str, &Entry)> = BTreeMap::new();
+    for entry in archive
+        .all_entries()
+        .iter()
+        .filter(|entry| is_carried(entry))
+    {
+        if let Some(file_name) = entry.path.file_name().and_then(|name| name.to_str()) {
+            scripts.insert(file_name, (version, entry));
+        }
+    }
+
+    let mut added = Vec::new();
+    for (old_version, old_archive) in history.iter().rev() {
+        for entry in old_archive
+            .all_entries()
+            .iter()
+            .filter(|entry| is_carried(entry))
+        {
+            let Some(file_name) = entry.path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+
+            match scripts.get(file_name) {
+                Some((kept_version, kept)) if kept.contents != entry.contents => {
+                    eprintln!(
+                        "{extension_name}: {file_name} differs between versions {old_version} and {kept_version}, keeping the one of {kept_version}"
+                    );
+                }
+                Some(_) => {}
+                None => {
+                    scripts.insert(file_name, (old_version, entry));
+                    added.push(entry.clone());
+                }
+            }
+        }
+    }
+
+    let count = added.len();
+    for entry in added {
+        archive.push(entry);
+    }
+
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        unarchiver::{Archive, Entry, EntryKind},
+        upgrade_graph::UpgradeGraph,
+    };
+
+    use super::{merge_scripts, missing_versions, CarriedScripts};
+
+    fn archive(files: &[(&str, &str)]) -> Archive {
+        let mut archive = Archive::default();
+        for (path, contents) in files {
+            archive.push(Entry {
+                path: format!("extension/{path}").into(),
+                contents: contents.as_bytes().to_vec(),
+                mtime: 0,
+                kind: EntryKind::Regular,
+                mode: 0o644,
+                link_target: None,
+            });
+        }
+        archive
+    }
+
+    fn contents<'a>(archive: &'a Archive, file_name: &str) -> Option<&'a [u8]> {
+        archive
+            .all_entries()
+            .iter()
+            .find(|entry| entry.path.file_name().unwrap() == file_name)
+            .map(|entry| entry.contents.as_slice())
+    }
+
+    fn history() -> Vec<(String, Archive)> {
+        vec![
+            (
+                "1.0".into(),
+                archive(&[("foo.control", ""), ("foo--1.0.sql", "-- 1.0")]),
+            ),
+            (
+                "1.1".into(),
+                archive(&[
+                    ("foo.control", ""),
+                    ("foo--1.1.sql", "-- 1.1"),
+                    ("foo--1.0--1.1.sql", "-- from 1.0, as of 1.1"),
+                    ("bar--0.1--0.2.sql", "-- not shipped anymore"),
+                ]),
+            ),
+            (
+                "1.2".into(),
+                archive(&[
+                    ("foo.control", ""),
+                    ("foo--1.2.sql", "-- 1.2"),
+                    ("foo--1.0--1.1.sql", "-- from 1.0, as of 1.2"),
+                    ("foo--1.1--1.2.sql", "-- from 1.1, as of 1.2"),
+                ]),
+            ),
+        ]
+    }
+
+    #[test]
+    fn newest_scripts_win() {
+        let mut current = archive(&[
+            ("foo.control", ""),
+            ("foo--2.0.sql", "-- 2.0"),
+            ("foo--1.1--1.2.sql", "-- from 1.1, as of 2.0"),
+            ("foo--1.2--2.0.sql", "-- from 1.2"),
+        ]);
+
+        let count = merge_scripts(
+            "foo",
+            "2.0",
+            &mut current,
+            &history(),
+            CarriedScripts::Upgrades,
+        );
+
+        assert_eq!(count, 1);
+        assert_eq!(
+            contents(&current, "foo--1.0--1.1.sql"),
+            Some(b"-- from 1.0, as of 1.2".as_slice())
+        );
+        assert_eq!(
+            contents(&current, "foo--1.1--1.2.sql"),
+            Some(b"-- from 1.1, as of 2.0".as_slice())
+        );
+        assert_eq!(contents(&current, "foo--1.0.sql"), None);
+        assert_eq!(contents(&current, "bar--0.1--0.2.sql"), None);
+    }
+
+    #[test]
+    fn install_scripts() {
+        let mut current = archive(&[("foo.control", ""), ("foo--2.0.sql", "-- 2.0")]);
+
+        let count = merge_scripts("foo", "2.0", &mut current, &history(), CarriedScripts::All);
+
+        assert_eq!(count, 5);
+        for version in ["1.0", "1.1", "1.2"] {
+            assert_eq!(
+                contents(&current, &format!("foo--{version}.sql")),
+                Some(format!("-- {version}").as_bytes())
+            );
+        }
+        assert_eq!(
+            contents(&current, "foo--1.0--1.1.sql"),
+            Some(b"-- from 1.0, as of 1.2".as_slice())
+        );
+    }
+
+    #[test]
+    fn versions_to_fetch() {
+        let older_versions = ["1.2", "1.1", "1.0"];
+        let mut graph = UpgradeGraph::from_script_names(["2.0", "1.2--2.0"].into_iter());
+
+        assert_eq!(
+            missing_versions(&graph, &older_versions, "2.0", CarriedScripts::Upgrades),
+            ["1.1", "1.0"]
+        );
+
+        // The archive of 1.2 holds every upgrade script
+        graph.add_archive(&history()[2].1, "foo");
+        assert!(
+            missing_versions(&graph, &older_versions, "2.0", CarriedScripts::Upgrades).is_empty()
+        );
+        // but not the install scripts of older versions
+        assert_eq!(
+            missing_versions(&graph, &older_versions, "2.0", CarriedScripts::All),
+            ["1.1", "1.0"]
+        );
+    }
+}
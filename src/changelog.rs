@@ -0,0 +1,141 @@
+use std::{fmt::Write, ops::Not};
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
+
+use crate::{
+    client::{Extension, ExtensionVersion},
+    compression::Compression,
+    utils, Result,
+};
+
+/// Signs the changelog when no maintainer was configured
+const UNKNOWN_MAINTAINER: &str = "Unknown <unknown@localhost>";
+
+struct ChangelogEntry {
+    version: String,
+    date: DateTime<FixedOffset>,
+    changes: Vec<String>,
+}
+
+/// A Debian changelog built from the versions of an extension known to the registry.
+///
+/// Docs.: <https://www.debian.org/doc/debian-policy/ch-source.html#debian-changelog-debian-changelog>
+pub struct Changelog<'a> {
+    package: &'a str,
+    maintainer: &'a str,
+    /// Newest version first
+    entries: Vec<ChangelogEntry>,
+}
+
+impl<'a> Changelog<'a> {
+    /// Build the changelog of `extension` up to the version being packaged. Versions with no
+    /// known release date are given `build_time`.
+    pub fn new(
+        package: &'a str,
+        extension: &Extension,
+        versions: &[ExtensionVersion],
+        maintainer: Option<&'a str>,
+        build_time: u64,
+    ) -> Self {
+        let fallback_date = DateTime::<Utc>::from_timestamp(build_time as i64, 0)
+            .unwrap_or_default()
+            .fixed_offset();
+
+        let mut entries: Vec<_> = versions
+            .iter()
+            .filter(|version| {
+                utils::compare_versions(&version.version, &extension.latest_version).is_le()
+            })
+            .map(|version| ChangelogEntry {
+                version: version.version.clone(),
+                date: version
+                    .created_at
+                    .as_deref()
+                    .or(version.updated_at.as_deref())
+                    .and_then(Self::parse_date)
+                    .unwrap_or(fallback_date),
+                changes: Self::changes(version),
+            })
+            .collect();
+
+        // The registry may not know about the version being packaged, e.g. when packaging a local file
+        let has_latest = entries
+            .iter()
+            .any(|entry| entry.version == extension.latest_version);
+        if has_latest.not() {
+            entries.push(ChangelogEntry {
+                version: extension.latest_version.clone(),
+                date: fallback_date,
+                changes: vec![format!(
+                    "New upstream release {}.",
+                    extension.latest_version
+                )],
+            });
+        }
+
+        entries.sort_by(|a, b| utils::compare_versions(&b.version, &a.version));
+        entries.dedup_by(|a, b| a.version == b.version);
+
+        Self {
+            package,
+            maintainer: maintainer.unwrap_or(UNKNOWN_MAINTAINER),
+            entries,
+        }
+    }
+
+    fn changes(version: &ExtensionVersion) -> Vec<String> {
+        let mut changes = vec![format!("New upstream release {}.", version.version)];
+
+        if let Some(notes) = &version.release_notes {
+            changes.extend(
+                notes
+                    .lines()
+                    .map(|line| line.trim().trim_start_matches(['*', '-']).trim())
+                    .filter(|line| line.is_empty().not())
+                    .map(str::to_owned),
+            );
+        }
+
+        changes
+    }
+
+    /// Parse the registry's timestamps, which may or may not carry a time zone
+    fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+        DateTime::parse_from_rfc3339(date).ok().or_else(|| {
+            NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%.f")
+                .ok()
+                .map(|naive| naive.and_utc().fixed_offset())
+        })
+    }
+
+    pub fn render(&self) -> Result<String> {
+        let mut changelog = String::with_capacity(256 * self.entries.len());
+
+        for entry in &self.entries {
+            writeln!(
+                changelog,
+                "{} ({}) unstable; urgency=medium",
+                self.package, entry.version
+            )?;
+            writeln!(changelog)?;
+            for change in &entry.changes {
+                writeln!(changelog, "  * {change}")?;
+            }
+            writeln!(changelog)?;
+            writeln!(
+                changelog,
+                " -- {}  {}",
+                self.maintainer,
+                entry.date.format("%a, %d %b %Y %H:%M:%S %z")
+            )?;
+            writeln!(changelog)?;
+        }
+
+        Ok(changelog)
+    }
+
+    /// The contents of `changelog.Debian.gz`, compressed like `gzip -9n` would
+    pub fn to_gzip(&self) -> Result<Vec<u8>> {
+        Compression::Gzip.compress(self.render()?.as_bytes(), Some(9))
+    }
+}
@@ -1,7 +1,11 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, str::FromStr};
 
 use argh::FromArgs;
 
+use crate::compression::Compression;
+use crate::dependency_graph::GraphFormat;
+use crate::lint::{LintFormat, Severity};
+
 #[derive(FromArgs, PartialEq, Debug)]
 /// Packages Trunk extensions into .deb files
 struct Args {
@@ -15,6 +19,10 @@ pub enum Subcommands {
     ShowSharedObjects(ShowSharedObjects),
     PackageAll(PackageAll),
     PackageOne(PackageOne),
+    Inspect(Inspect),
+    Lint(Lint),
+    Report(Report),
+    WhoNeeds(WhoNeeds),
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -23,37 +31,300 @@ pub enum Subcommands {
 pub struct ShowSharedObjects {
     #[argh(option)]
     /// the base URL of the Trunk provider
-    base_url: String,
+    pub base_url: String,
+    #[argh(option, default = "16")]
+    /// the PostgreSQL major version whose archives to analyze
+    pub pg_version: u16,
+    #[argh(option, default = "GraphFormat::Text")]
+    /// output format of the dependency graphs: text, json or dot
+    pub format: GraphFormat,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Report on the extensions of the registry
+#[argh(subcommand, name = "report")]
+pub struct Report {
+    #[argh(subcommand)]
+    pub report: Reports,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
-/// Package all extensions into .deb
-#[argh(subcommand, name = "package-all")]
-pub struct PackageAll {
+#[argh(subcommand)]
+pub enum Reports {
+    UnknownSonames(UnknownSonames),
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// List the sonames whose package is unknown by how many extensions they keep from being packaged
+#[argh(subcommand, name = "unknown-sonames")]
+pub struct UnknownSonames {
     #[argh(option)]
-    /// the base URL of the Trunk provider
-    pub base_url: String,
+    /// the base URL of the Trunk provider to analyze the extensions of
+    pub base_url: Option<String>,
+    #[argh(option)]
+    /// directory of Trunk archives named like `pg_cron-1.6.2.tar.gz` to analyze instead
+    pub mirror: Option<PathBuf>,
+    #[argh(option, default = "16")]
+    /// the PostgreSQL major version whose archives to analyze
+    pub pg_version: u16,
+    #[argh(option)]
+    /// file of `soname package` lines, like `libraries-found`, for sonames the built-in table lacks
+    pub mappings: Option<PathBuf>,
     #[argh(option)]
-    /// the directory in which to export the generated packages
-    pub export_dir: PathBuf,
+    /// a Debian `Packages` index, possibly compressed with gzip or xz, to suggest packages from;
+    /// may be given several times
+    pub packages_index: Vec<PathBuf>,
+    #[argh(option)]
+    /// file to export the unknown sonames to, as `soname package` lines naming the best suggestion
+    pub output: Option<PathBuf>,
+    #[argh(switch)]
+    /// comment the exported lines out, so that each suggestion has to be checked and uncommented
+    /// before the file is used as --mappings
+    pub commented: bool,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
-/// Package a single extension into a .deb
-#[argh(subcommand, name = "package-one")]
-pub struct PackageOne {
-    #[argh(option)]
-    /// the base URL of the Trunk provider
-    pub base_url: String,
+/// Show which extension packages need a library or package
+#[argh(subcommand, name = "who-needs")]
+pub struct WhoNeeds {
     #[argh(positional)]
-    /// the Trunk project to be packaged
-    pub trunk_project_name: String,
+    /// a soname such as `libcurl.so.4`, or a package such as `libcurl4`
+    pub name: String,
+    #[argh(option, default = "PathBuf::from(\"dependency-index.json\")")]
+    /// the dependency index to query, rebuilt first when --base-url or --mirror is given
+    pub index: PathBuf,
+    #[argh(option)]
+    /// the base URL of the Trunk provider to build the index from
+    pub base_url: Option<String>,
     #[argh(option)]
-    /// the directory in which to export the generated package
-    pub export_dir: PathBuf,
+    /// directory of Trunk archives named like `pg_cron-1.6.2.tar.gz` to build the index from
+    pub mirror: Option<PathBuf>,
+    #[argh(option, default = "16")]
+    /// the PostgreSQL major version whose archives to index
+    pub pg_version: u16,
     #[argh(option)]
-    /// the path to a Trunk package
-    pub file: Option<PathBuf>,
+    /// a Debian `Packages` index, possibly compressed with gzip or xz, whose dependencies between
+    /// packages are indexed too; may be given several times
+    pub packages_index: Vec<PathBuf>,
+}
+
+/// Declares a command which builds packages: its own options, followed by the options every such
+/// command takes, which `packaging_args` gathers. argh has no way to flatten a struct of options
+/// into another, and only recognizes `Option` and `Vec` fields when their types are spelled out,
+/// hence the types being matched token by token.
+macro_rules! packaging_command {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[$field_attr:meta])*
+                pub $field:ident: $ty:ident $(<$inner:ident>)?,
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        pub struct $name {
+            $(
+                $(#[$field_attr])*
+                pub $field: $ty $(<$inner>)?,
+            )*
+            #[argh(option, default = "Compression::Gzip")]
+            /// compression of the .deb members: gzip, xz, zstd or none
+            pub compression: Compression,
+            #[argh(option)]
+            /// compression level, defaults to the codec's own default
+            pub compression_level: Option<u32>,
+            #[argh(option)]
+            /// file holding `pg_config` output describing the install layout, Debian's layout by default
+            pub layout: Option<PathBuf>,
+            #[argh(option)]
+            /// TOML file of install rules tried before the built-in ones
+            pub rules: Option<PathBuf>,
+            #[argh(switch)]
+            /// package extensions with neither a registry license nor license files
+            pub allow_missing_license: bool,
+            #[argh(option)]
+            /// maintainer of the packages as `Name <email>`, read from DEBFULLNAME and DEBEMAIL by default
+            pub maintainer: Option<String>,
+            #[argh(option, default = "PgVersions::default()")]
+            /// comma-separated PostgreSQL major versions to build packages for, e.g. `14,15,16,17`
+            pub pg_versions: PgVersions,
+            #[argh(switch)]
+            /// keep debug information in shared objects instead of moving it to -dbgsym packages
+            pub no_dbgsym: bool,
+            #[argh(switch)]
+            /// remove symbol tables, comments and debug info from shipped shared objects
+            pub strip: bool,
+            #[argh(switch)]
+            /// lint the generated packages, failing on errors
+            pub lint: bool,
+            #[argh(option)]
+            /// TOML file of hardening properties shared objects must have or may not lose
+            pub hardening_policy: Option<PathBuf>,
+            #[argh(switch)]
+            /// merge the upgrade scripts older published versions need into the package
+            pub carry_forward: bool,
+            #[argh(switch)]
+            /// also merge the install scripts of older versions, implies --carry-forward
+            pub carry_install_scripts: bool,
+            #[argh(switch)]
+            /// ship an example conf.d snippet for extensions which have to be preloaded
+            pub preload_snippet: bool,
+            #[argh(option)]
+            /// codename of the distribution release to name dependencies for, e.g. `noble`, instead of
+            /// naming alternatives which suit several releases
+            pub distro: Option<String>,
+            #[argh(option)]
+            /// a Debian `Packages` index, possibly compressed with gzip or xz, which every dependency must
+            /// resolve against; may be given several times
+            pub packages_index: Vec<PathBuf>,
+            #[argh(option)]
+            /// file of `soname package` lines, like `libraries-found`, for sonames the built-in table lacks
+            pub mappings: Option<PathBuf>,
+            #[argh(switch)]
+            /// append commented-out stubs for the unknown sonames to the --mappings file
+            pub write_missing: bool,
+        }
+
+        impl $name {
+            pub fn packaging_args(&self) -> PackagingArgs {
+                PackagingArgs {
+                    compression: self.compression,
+                    compression_level: self.compression_level,
+                    layout: self.layout.clone(),
+                    rules: self.rules.clone(),
+                    allow_missing_license: self.allow_missing_license,
+                    maintainer: self.maintainer.clone(),
+                    pg_versions: self.pg_versions.clone(),
+                    no_dbgsym: self.no_dbgsym,
+                    strip: self.strip,
+                    lint: self.lint,
+                    hardening_policy: self.hardening_policy.clone(),
+                    carry_forward: self.carry_forward,
+                    carry_install_scripts: self.carry_install_scripts,
+                    preload_snippet: self.preload_snippet,
+                    distro: self.distro.clone(),
+                    packages_index: self.packages_index.clone(),
+                    mappings: self.mappings.clone(),
+                    write_missing: self.write_missing,
+                }
+            }
+        }
+    };
+}
+
+packaging_command! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    /// Package all extensions into .deb
+    #[argh(subcommand, name = "package-all")]
+    pub struct PackageAll {
+        #[argh(option)]
+        /// the base URL of the Trunk provider
+        pub base_url: String,
+        #[argh(option)]
+        /// the directory in which to export the generated packages
+        pub export_dir: PathBuf,
+    }
+}
+
+packaging_command! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    /// Package a single extension into a .deb
+    #[argh(subcommand, name = "package-one")]
+    pub struct PackageOne {
+        #[argh(option)]
+        /// the base URL of the Trunk provider
+        pub base_url: String,
+        #[argh(positional)]
+        /// the Trunk project to be packaged
+        pub trunk_project_name: String,
+        #[argh(option)]
+        /// the directory in which to export the generated package
+        pub export_dir: PathBuf,
+        #[argh(option)]
+        /// the path to a Trunk package
+        pub file: Option<PathBuf>,
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Show the control fields and contents of a .deb
+#[argh(subcommand, name = "inspect")]
+pub struct Inspect {
+    #[argh(positional)]
+    /// the package to inspect
+    pub deb: PathBuf,
+    #[argh(switch)]
+    /// print JSON instead of text
+    pub json: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Check packages for policy problems
+#[argh(subcommand, name = "lint")]
+pub struct Lint {
+    #[argh(positional)]
+    /// the packages to check
+    pub debs: Vec<PathBuf>,
+    #[argh(option, default = "LintFormat::Text")]
+    /// output format: text, json or sarif
+    pub format: LintFormat,
+    #[argh(option, default = "Severity::Error")]
+    /// exit with an error when finding problems this severe: info, warning or error
+    pub fail_on: Severity,
+}
+
+/// The PostgreSQL major versions to build packages for
+#[derive(Clone, PartialEq, Debug)]
+pub struct PgVersions(pub Vec<u16>);
+
+impl Default for PgVersions {
+    fn default() -> Self {
+        Self(vec![16])
+    }
+}
+
+impl FromStr for PgVersions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut versions = s
+            .split(',')
+            .map(str::trim)
+            .map(|version| {
+                version
+                    .parse()
+                    .map_err(|_| format!("`{version}` is not a PostgreSQL major version"))
+            })
+            .collect::<Result<Vec<u16>, _>>()?;
+
+        versions.sort_unstable();
+        versions.dedup();
+
+        Ok(Self(versions))
+    }
+}
+
+/// Options shared by the commands which build packages
+pub struct PackagingArgs {
+    pub compression: Compression,
+    pub compression_level: Option<u32>,
+    pub layout: Option<PathBuf>,
+    pub rules: Option<PathBuf>,
+    pub allow_missing_license: bool,
+    pub maintainer: Option<String>,
+    pub pg_versions: PgVersions,
+    pub no_dbgsym: bool,
+    pub strip: bool,
+    pub lint: bool,
+    pub hardening_policy: Option<PathBuf>,
+    pub carry_forward: bool,
+    pub carry_install_scripts: bool,
+    pub preload_snippet: bool,
+    pub distro: Option<String>,
+    pub packages_index: Vec<PathBuf>,
+    pub mappings: Option<PathBuf>,
+    pub write_missing: bool,
 }
 
 pub fn parse_args() -> Subcommands {
@@ -1,10 +1,13 @@
-use crate::Result;
+use crate::{
+    unarchiver::{Archive, Unarchiver},
+    Result,
+};
 
 use std::sync::Arc;
 
 use anyhow::bail;
 use bytes::Bytes;
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize};
 
 #[derive(Clone)]
 pub struct Client {
@@ -12,7 +15,7 @@ pub struct Client {
     base_url: Arc<str>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Extension {
     pub name: String,
@@ -21,6 +24,29 @@ pub struct Extension {
     pub description: Option<String>,
 }
 
+/// A single published version of an extension
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtensionVersion {
+    pub version: String,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    /// Upstream release notes, when the publisher supplied them
+    pub release_notes: Option<String>,
+}
+
+impl Extension {
+    /// An extension known only by an archive, e.g. one of a mirror
+    pub fn unpublished(name: String, latest_version: String) -> Self {
+        Self {
+            name,
+            license: None,
+            latest_version,
+            description: None,
+        }
+    }
+}
+
 impl Client {
     pub fn new(base_url: String) -> Self {
         Self {
@@ -33,8 +59,20 @@ impl Client {
     pub async fn fetch_extensions(&self) -> Result<Vec<Extension>> {
         let url = format!("{}/extensions/all", self.base_url);
 
-        println!("Will hit {url}");
+        eprintln!("Will hit {url}");
+
+        self.fetch_json(url).await
+    }
 
+    /// Get every version of the given extension published to the registry
+    pub async fn fetch_extension_versions(&self, extension: &str) -> Result<Vec<ExtensionVersion>> {
+        let url = format!("{}/extensions/detail/{}", self.base_url, extension);
+
+        self.fetch_json(url).await
+    }
+
+    /// Get a JSON document of the registry, failing on an unsuccessful response
+    async fn fetch_json<T: DeserializeOwned>(&self, url: String) -> Result<T> {
         let response = self.client.get(url).send().await?;
 
         let status = response.status();
@@ -48,6 +86,16 @@ impl Client {
         }
     }
 
+    /// Like [`Client::fetch_extension_versions`], but falls back to an empty history on failure
+    pub async fn version_history(&self, extension: &str) -> Vec<ExtensionVersion> {
+        self.fetch_extension_versions(extension)
+            .await
+            .unwrap_or_else(|err| {
+                eprintln!("Failed to fetch the version history of {extension}: {err}");
+                Vec::new()
+            })
+    }
+
     pub async fn download_file(&self, url: &str) -> Result<Bytes> {
         let response = self.client.get(url).send().await?;
 
@@ -56,13 +104,42 @@ impl Client {
         Ok(content)
     }
 
-    pub async fn fetch_extension_archive(&self, extension: &str) -> Result<Bytes> {
+    /// Download the latest archive of `extension` built against PostgreSQL `pg_major`
+    pub async fn fetch_extension_archive(&self, extension: &str, pg_major: u16) -> Result<Bytes> {
+        self.fetch_extension_version_archive(extension, "latest", pg_major)
+            .await
+    }
+
+    /// Download the archive of a given version of `extension` built against PostgreSQL `pg_major`
+    pub async fn fetch_extension_version_archive(
+        &self,
+        extension: &str,
+        version: &str,
+        pg_major: u16,
+    ) -> Result<Bytes> {
         let archive_url = {
-            let url = format!("{}/extensions/{}/latest/download", self.base_url, extension);
+            let url = format!(
+                "{}/extensions/{}/{}/download?pg_version={pg_major}",
+                self.base_url, extension, version
+            );
 
             self.client.get(url).send().await?.text().await?
         };
 
         self.download_file(&archive_url).await
     }
+
+    /// Download and decompress the archive of a given version of `extension`
+    pub async fn fetch_version_archive(
+        &self,
+        extension: &str,
+        version: &str,
+        pg_major: u16,
+    ) -> Result<Archive> {
+        let tar_gz = self
+            .fetch_extension_version_archive(extension, version, pg_major)
+            .await?;
+
+        Unarchiver::decompress_in_memory(&tar_gz)
+    }
 }
@@ -0,0 +1,198 @@
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+    str::FromStr,
+};
+
+use anyhow::bail;
+use flate2::{read::GzDecoder, GzBuilder};
+use serde::Serialize;
+
+use crate::Result;
+
+/// The codecs a .deb's `control.tar` and `data.tar` members may be compressed with
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Compression {
+    #[default]
+    Gzip,
+    Xz,
+    Zstd,
+    None,
+}
+
+impl Compression {
+    /// The suffix appended to the name of the ar members, e.g. `.zst` in `data.tar.zst`
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => ".gz",
+            Compression::Xz => ".xz",
+            Compression::Zstd => ".zst",
+            Compression::None => "",
+        }
+    }
+
+    /// The codec of an ar member, from its name, e.g. `data.tar.xz`
+    pub fn from_member_name(name: &str) -> Option<Self> {
+        let compression = match name.rsplit_once(".tar")?.1 {
+            ".gz" => Compression::Gzip,
+            ".xz" => Compression::Xz,
+            ".zst" => Compression::Zstd,
+            "" => Compression::None,
+            _ => return None,
+        };
+
+        Some(compression)
+    }
+
+    fn default_level(self) -> u32 {
+        match self {
+            Compression::Gzip => 6,
+            Compression::Xz => 6,
+            Compression::Zstd => 3,
+            Compression::None => 0,
+        }
+    }
+
+    fn max_level(self) -> u32 {
+        match self {
+            Compression::Gzip | Compression::Xz => 9,
+            Compression::Zstd => 22,
+            Compression::None => 0,
+        }
+    }
+
+    /// Fails if `level` is not supported by this codec
+    pub fn check_level(self, level: u32) -> Result {
+        if level > self.max_level() {
+            bail!(
+                "Compression level {level} is out of range for {self} (max. {})",
+                self.max_level()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Compress `bytes` with this codec, using its default level if none is given
+    pub fn compress(self, bytes: &[u8], level: Option<u32>) -> Result<Vec<u8>> {
+        let level = level.unwrap_or_else(|| self.default_level());
+        self.check_level(level)?;
+
+        match self {
+            Compression::Gzip => Self::gzip(bytes, level),
+            Compression::Xz => Self::xz(bytes, level),
+            Compression::Zstd => Self::zstd(bytes, level),
+            Compression::None => Ok(bytes.to_vec()),
+        }
+    }
+
+    pub fn decompress(self, bytes: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::Gzip => {
+                let mut decompressed = Vec::with_capacity(bytes.len() * 4);
+                GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+
+                Ok(decompressed)
+            }
+            Compression::Xz => Self::unxz(bytes),
+            Compression::Zstd => Self::unzstd(bytes),
+            Compression::None => Ok(bytes.to_vec()),
+        }
+    }
+
+    fn gzip(bytes: &[u8], level: u32) -> Result<Vec<u8>> {
+        // Leave the timestamp and file name out of the header so that the output only depends on its input
+        let mut encoder = GzBuilder::new()
+            .mtime(0)
+            .write(Vec::with_capacity(2048), flate2::Compression::new(level));
+
+        encoder.write_all(bytes)?;
+
+        Ok(encoder.finish()?)
+    }
+
+    #[cfg(feature = "xz")]
+    fn xz(bytes: &[u8], level: u32) -> Result<Vec<u8>> {
+        let mut encoder = xz2::write::XzEncoder::new(Vec::with_capacity(2048), level);
+
+        encoder.write_all(bytes)?;
+
+        Ok(encoder.finish()?)
+    }
+
+    #[cfg(not(feature = "xz"))]
+    fn xz(_: &[u8], _: u32) -> Result<Vec<u8>> {
+        bail!("trunk-packager was built without xz support (feature `xz`)")
+    }
+
+    #[cfg(feature = "xz")]
+    fn unxz(bytes: &[u8]) -> Result<Vec<u8>> {
+        let mut decompressed = Vec::with_capacity(bytes.len() * 4);
+        xz2::read::XzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+
+        Ok(decompressed)
+    }
+
+    #[cfg(not(feature = "xz"))]
+    fn unxz(_: &[u8]) -> Result<Vec<u8>> {
+        bail!("trunk-packager was built without xz support (feature `xz`)")
+    }
+
+    #[cfg(feature = "zstd")]
+    fn zstd(bytes: &[u8], level: u32) -> Result<Vec<u8>> {
+        Ok(zstd::encode_all(bytes, level as i32)?)
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn zstd(_: &[u8], _: u32) -> Result<Vec<u8>> {
+        bail!("trunk-packager was built without zstd support (feature `zstd`)")
+    }
+
+    #[cfg(feature = "zstd")]
+    fn unzstd(bytes: &[u8]) -> Result<Vec<u8>> {
+        Ok(zstd::decode_all(bytes)?)
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn unzstd(_: &[u8]) -> Result<Vec<u8>> {
+        bail!("trunk-packager was built without zstd support (feature `zstd`)")
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(codec: &str) -> std::result::Result<Self, Self::Err> {
+        match codec {
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "xz" => Ok(Self::Xz),
+            "zstd" | "zst" => Ok(Self::Zstd),
+            "none" => Ok(Self::None),
+            other => Err(format!(
+                "unknown compression `{other}`, expected one of gzip, xz, zstd or none"
+            )),
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Compression::Gzip => "gzip",
+            Compression::Xz => "xz",
+            Compression::Zstd => "zstd",
+            Compression::None => "none",
+        };
+
+        f.write_str(name)
+    }
+}
+
+impl Serialize for Compression {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
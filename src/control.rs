@@ -0,0 +1,216 @@
+use std::{fmt::Write, ops::Not};
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+
+use crate::Result;
+
+/// The fields of a binary package's `control` file
+///
+/// Docs.: <https://www.debian.org/doc/debian-policy/ch-controlfields.html#binary-package-control-files-debian-control>
+#[derive(Serialize, Clone, PartialEq, Eq, Debug, Default)]
+pub struct ControlFields {
+    pub package: String,
+    /// Set on automatically generated packages, such as `-dbgsym` ones
+    pub auto_built_package: Option<String>,
+    pub section: Option<String>,
+    pub architecture: String,
+    pub version: String,
+    pub maintainer: Option<String>,
+    /// The synopsis, followed by the extended description if any
+    pub description: String,
+    pub homepage: Option<String>,
+    /// Relations as they're written down, e.g. `postgresql-16-foo (= 1.0)`
+    pub depends: Vec<String>,
+    pub recommends: Vec<String>,
+    pub suggests: Vec<String>,
+    pub build_ids: Vec<String>,
+    /// Fields we don't model, in the order they appear
+    pub other: Vec<(String, String)>,
+}
+
+impl ControlFields {
+    pub fn render(&self) -> Result<String> {
+        let mut control = String::with_capacity(512);
+
+        writeln!(control, "Package: {}", self.package)?;
+        if let Some(auto_built) = &self.auto_built_package {
+            writeln!(control, "Auto-Built-Package: {auto_built}")?;
+        }
+        if let Some(section) = &self.section {
+            writeln!(control, "Section: {section}")?;
+        }
+        writeln!(control, "Architecture: {}", self.architecture)?;
+        writeln!(control, "Version: {}", self.version)?;
+        if let Some(maintainer) = &self.maintainer {
+            writeln!(control, "Maintainer: {maintainer}")?;
+        }
+        Self::write_multiline(&mut control, "Description", &self.description)?;
+        if let Some(homepage) = &self.homepage {
+            writeln!(control, "Homepage: {homepage}")?;
+        }
+        for (name, relations) in [
+            ("Depends", &self.depends),
+            ("Recommends", &self.recommends),
+            ("Suggests", &self.suggests),
+        ] {
+            if relations.is_empty().not() {
+                writeln!(control, "{name}: {}", relations.join(", "))?;
+            }
+        }
+        if self.build_ids.is_empty().not() {
+            writeln!(control, "Build-Ids: {}", self.build_ids.join(" "))?;
+        }
+        for (name, value) in &self.other {
+            Self::write_multiline(&mut control, name, value)?;
+        }
+
+        Ok(control)
+    }
+
+    /// Write a field whose value may span several lines, with empty lines written as ` .`
+    fn write_multiline(control: &mut String, name: &str, value: &str) -> Result {
+        let mut lines = value.lines();
+        writeln!(control, "{name}: {}", lines.next().unwrap_or(""))?;
+
+        for line in lines {
+            if line.trim().is_empty() {
+                writeln!(control, " .")?;
+            } else {
+                writeln!(control, " {line}")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse a `control` file, such as the ones [`ControlFields::render`] writes
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut fields: Vec<(String, String)> = Vec::new();
+
+        for line in text.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if line.starts_with([' ', '\t']) {
+                let (_, value) = fields
+                    .last_mut()
+                    .with_context(|| format!("Continuation line before any field: {line}"))?;
+                let line = line.trim();
+
+                value.push('\n');
+                if line != "." {
+                    value.push_str(line);
+                }
+                continue;
+            }
+
+            let Some((name, value)) = line.split_once(':') else {
+                bail!("Malformed control line: {line}");
+            };
+            fields.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+
+        let mut control = Self::default();
+
+        for (name, value) in fields {
+            match name.to_ascii_lowercase().as_str() {
+                "package" => control.package = value,
+                "auto-built-package" => control.auto_built_package = Some(value),
+                "section" => control.section = Some(value),
+                "architecture" => control.architecture = value,
+                "version" => control.version = value,
+                "maintainer" => control.maintainer = Some(value),
+                "description" => control.description = value,
+                "homepage" => control.homepage = Some(value),
+                "depends" => control.depends = Self::parse_relations(&value),
+                "recommends" => control.recommends = Self::parse_relations(&value),
+                "suggests" => control.suggests = Self::parse_relations(&value),
+                "build-ids" => {
+                    control.build_ids = value.split_whitespace().map(str::to_owned).collect()
+                }
+                _ => control.other.push((name, value)),
+            }
+        }
+
+        anyhow::ensure!(
+            control.package.is_empty().not(),
+            "Control file has no Package field"
+        );
+
+        Ok(control)
+    }
+
+    fn parse_relations(value: &str) -> Vec<String> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|relation| relation.is_empty().not())
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ControlFields;
+
+    fn fields() -> ControlFields {
+        ControlFields {
+            package: "postgresql-16-foo".into(),
+            auto_built_package: None,
+            section: Some("database".into()),
+            architecture: "amd64".into(),
+            version: "1.2.0".into(),
+            maintainer: Some("Jane Doe <jane@example.com>".into()),
+            description: "Foo for PostgreSQL\nDoes foo.\n\nAnd bar too.".into(),
+            homepage: Some("https://example.com/foo".into()),
+            depends: vec![
+                "postgresql-16".into(),
+                "libssl3 (>= 3.0.2) | libssl3t64".into(),
+            ],
+            recommends: vec!["libcurl4".into()],
+            suggests: Vec::new(),
+            build_ids: vec!["0123abcd".into(), "4567ef01".into()],
+            other: vec![
+                ("X-Postgres-Upgradable-From".into(), "1.0 1.1".into()),
+                ("X-Notes".into(), "first\n\nsecond".into()),
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let fields = fields();
+        let rendered = fields.render().unwrap();
+
+        assert_eq!(ControlFields::parse(&rendered).unwrap(), fields);
+        assert!(
+            rendered.contains("Description: Foo for PostgreSQL\n Does foo.\n .\n And bar too.\n")
+        );
+    }
+
+    #[test]
+    fn parse_written_by_hand() {
+        let control = "Package: postgresql-16-foo-dbgsym\n\
+                       Auto-Built-Package: debug-symbols\n\
+                       architecture: amd64\n\
+                       Version: 1.2.0\n\
+                       Description: debug symbols for postgresql-16-foo\n\
+                       Depends: postgresql-16-foo (= 1.2.0),\n  libc6\n\
+                       \n";
+        let fields = ControlFields::parse(control).unwrap();
+
+        assert_eq!(fields.auto_built_package.as_deref(), Some("debug-symbols"));
+        assert_eq!(fields.architecture, "amd64");
+        assert_eq!(fields.depends, ["postgresql-16-foo (= 1.2.0)", "libc6"]);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(ControlFields::parse("Version: 1.0\n").is_err());
+        assert!(ControlFields::parse(" continued\nPackage: foo\n").is_err());
+        assert!(ControlFields::parse("Package: foo\nno colon\n").is_err());
+    }
+}
@@ -0,0 +1,304 @@
+use std::{collections::BTreeSet, fmt::Write, ops::Not, path::Path};
+
+use anyhow::bail;
+
+use crate::{
+    client::Extension,
+    unarchiver::{Archive, Entry, EntryKind},
+    Result,
+};
+
+const FORMAT_URL: &str = "https://www.debian.org/doc/packaging-manuals/copyright-format/1.0/";
+
+/// File names, compared case-insensitively, which hold license texts
+const LICENSE_FILE_PREFIXES: [&str; 4] = ["license", "licence", "copying", "notice"];
+
+/// The license information of an extension, rendered as a machine-readable
+/// `debian/copyright` file (DEP-5).
+///
+/// Docs.: <https://www.debian.org/doc/packaging-manuals/copyright-format/1.0/>
+pub struct Copyright<'a> {
+    extension: &'a Extension,
+    /// The license files found in the Trunk archive
+    license_files: Vec<&'a Entry>,
+}
+
+impl<'a> Copyright<'a> {
+    /// Gather the license information of `extension`.
+    ///
+    /// Fails if neither the registry nor the archive know anything about its license, unless
+    /// `allow_missing_license` is set.
+    pub fn collect(
+        extension: &'a Extension,
+        archive: &'a Archive,
+        allow_missing_license: bool,
+    ) -> Result<Self> {
+        let license_files: Vec<_> = archive
+            .all_entries()
+            .iter()
+            .filter(|entry| entry.kind == EntryKind::Regular && is_license_file(&entry.path))
+            .collect();
+
+        if extension.license.is_none() && license_files.is_empty() && allow_missing_license.not() {
+            bail!(
+                "{} has no license metadata in the registry and ships no license file",
+                extension.name
+            );
+        }
+
+        Ok(Self {
+            extension,
+            license_files,
+        })
+    }
+
+    /// The license name used in the `License` fields, as DEP-5 spells license expressions
+    fn license_name(&self) -> String {
+        match &self.extension.license {
+            Some(license) => license.replace(" OR ", " or ").replace(" AND ", " and "),
+            None if self.license_files.is_empty() => "unknown".into(),
+            None => "other".into(),
+        }
+    }
+
+    /// Copyright holders, taken from the `Copyright ...` lines of the license texts
+    fn copyright_holders(&self) -> BTreeSet<String> {
+        let mut holders = BTreeSet::new();
+
+        for entry in &self.license_files {
+            let text = String::from_utf8_lossy(&entry.contents);
+            holders.extend(text.lines().filter_map(Self::copyright_holder));
+        }
+
+        holders
+    }
+
+    /// Extract `2023 Jane Doe` out of a line such as `Copyright (c) 2023 Jane Doe`
+    fn copyright_holder(line: &str) -> Option<String> {
+        let line = line.trim();
+        let prefix = line.get(.."copyright".len())?;
+        if prefix.eq_ignore_ascii_case("copyright").not() {
+            return None;
+        }
+
+        let holder = line["copyright".len()..]
+            .trim_start_matches([' ', ':'])
+            .trim_start_matches("(c)")
+            .trim_start_matches("(C)")
+            .trim_start_matches('©')
+            .trim();
+
+        // Skip prose such as "copyright notice" or "Copyright and license"
+        holder
+            .starts_with(|ch: char| ch.is_ascii_digit())
+            .then(|| holder.to_owned())
+    }
+
+    /// Render the `copyright` file
+    pub fn render(&self) -> Result<String> {
+        let name = &self.extension.name;
+        let license = self.license_name();
+        let mut copyright = String::with_capacity(2048);
+
+        writeln!(copyright, "Format: {FORMAT_URL}")?;
+        writeln!(copyright, "Upstream-Name: {name}")?;
+        writeln!(copyright, "Source: https://pgt.dev/extensions/{name}")?;
+        writeln!(copyright)?;
+
+        let holders = self.copyright_holders();
+        writeln!(copyright, "Files: *")?;
+        if holders.is_empty() {
+            writeln!(copyright, "Copyright: unknown")?;
+        } else {
+            let mut holders = holders.iter();
+            writeln!(copyright, "Copyright: {}", holders.next().unwrap())?;
+            for holder in holders {
+                writeln!(copyright, " {holder}")?;
+            }
+        }
+        writeln!(copyright, "License: {license}")?;
+
+        if self.extension.license.is_none() {
+            writeln!(
+                copyright,
+                "Comment: The Trunk registry has no license metadata for this extension"
+            )?;
+        }
+
+        if self.license_files.is_empty().not() {
+            writeln!(copyright)?;
+            writeln!(copyright, "License: {license}")?;
+
+            for (idx, entry) in self.license_files.iter().enumerate() {
+                if idx > 0 {
+                    writeln!(copyright, " .")?;
+                }
+                Self::write_license_text(&mut copyright, entry)?;
+            }
+        }
+
+        Ok(copyright)
+    }
+
+    /// Write a license text as a DEP-5 continuation: indented, with empty lines written as `.`
+    fn write_license_text(copyright: &mut String, entry: &Entry) -> Result {
+        let text = String::from_utf8_lossy(&entry.contents);
+
+        for line in text.trim_end().lines() {
+            let line = line.trim_end();
+
+            if line.is_empty() {
+                writeln!(copyright, " .")?;
+            } else {
+                writeln!(copyright, " {line}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the entry at `path` is a license file such as `LICENSE.md` or `licenses/COPYING`
+pub fn is_license_file(path: &Path) -> bool {
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+
+    LICENSE_FILE_PREFIXES.iter().any(|prefix| {
+        file_name
+            .get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ops::Not, path::Path};
+
+    use crate::{
+        client::Extension,
+        unarchiver::{Archive, Entry, EntryKind},
+    };
+
+    use super::{is_license_file, Copyright};
+
+    fn extension(license: Option<&str>) -> Extension {
+        Extension {
+            name: "myext".into(),
+            license: license.map(str::to_owned),
+            latest_version: "1.0".into(),
+            description: None,
+        }
+    }
+
+    fn archive(files: &[(&str, &str)]) -> Archive {
+        let mut archive = Archive::default();
+        for (path, contents) in files {
+            archive.push(Entry {
+                path: path.into(),
+                contents: contents.as_bytes().to_vec(),
+                mtime: 0,
+                kind: EntryKind::Regular,
+                mode: 0o644,
+                link_target: None,
+            });
+        }
+
+        archive
+    }
+
+    #[test]
+    fn license_files() {
+        for path in [
+            "LICENSE",
+            "licenses/LICENSE.md",
+            "Licence.txt",
+            "COPYING",
+            "doc/copying.LESSER",
+            "NOTICE",
+        ] {
+            assert!(is_license_file(Path::new(path)), "{path}");
+        }
+        for path in ["README.md", "LICENSE/README", "UNLICENSE"] {
+            assert!(is_license_file(Path::new(path)).not(), "{path}");
+        }
+    }
+
+    #[test]
+    fn dep5() {
+        let extension = extension(Some("Apache-2.0 OR MIT"));
+        let archive = archive(&[
+            (
+                "licenses/LICENSE",
+                "MIT License\n\nCopyright (c) 2023 Jane Doe\n",
+            ),
+            (
+                "NOTICE",
+                "Copyright: 2021-2022 ACME Corp.\nThe copyright notice\n",
+            ),
+            ("extension/myext.control", "default_version = '1.0'\n"),
+        ]);
+        let copyright = Copyright::collect(&extension, &archive, false).unwrap();
+
+        assert_eq!(
+            copyright.render().unwrap(),
+            "Format: https://www.debian.org/doc/packaging-manuals/copyright-format/1.0/\n\
+             Upstream-Name: myext\n\
+             Source: https://pgt.dev/extensions/myext\n\
+             \n\
+             Files: *\n\
+             Copyright: 2021-2022 ACME Corp.\n\
+             \x202023 Jane Doe\n\
+             License: Apache-2.0 or MIT\n\
+             \n\
+             License: Apache-2.0 or MIT\n\
+             \x20MIT License\n\
+             \x20.\n\
+             \x20Copyright (c) 2023 Jane Doe\n\
+             \x20.\n\
+             \x20Copyright: 2021-2022 ACME Corp.\n\
+             \x20The copyright notice\n"
+        );
+    }
+
+    #[test]
+    fn license_files_without_metadata() {
+        let extension = extension(None);
+        let archive = archive(&[("COPYING", "Some license\n")]);
+        let rendered = Copyright::collect(&extension, &archive, false)
+            .unwrap()
+            .render()
+            .unwrap();
+
+        assert!(
+            rendered.contains("Copyright: unknown\nLicense: other\n"),
+            "{rendered}"
+        );
+        assert!(rendered
+            .contains("Comment: The Trunk registry has no license metadata for this extension\n"));
+        assert!(
+            rendered.ends_with("License: other\n Some license\n"),
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn missing_license() {
+        let extension = extension(None);
+        let archive = archive(&[("README.md", "# myext\n")]);
+
+        let err = Copyright::collect(&extension, &archive, false)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "myext has no license metadata in the registry and ships no license file"
+        );
+
+        let rendered = Copyright::collect(&extension, &archive, true)
+            .unwrap()
+            .render()
+            .unwrap();
+        assert!(rendered.contains("License: unknown\n"), "{rendered}");
+    }
+}
@@ -1,45 +1,274 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::io::Cursor;
 use std::ops::Not;
+use std::path::PathBuf;
 use std::path::{Component, Path};
-use std::{io::Write, path::PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::Ok;
-use flate2::Compression;
+use anyhow::{Context, Ok};
 use fs_err::File;
 
-use crate::dependencies::{DependencySupplier, FetchData};
-use crate::unarchiver::{Archive, Entry};
-use crate::{client::Extension, dependencies::Dependencies};
-use crate::{utils, Result, TEMP_DIR};
+use crate::carry_forward::{self, CarriedScripts};
+use crate::changelog::Changelog;
+use crate::cli::{PackagingArgs, PgVersions};
+use crate::compression::Compression;
+use crate::control::ControlFields;
+use crate::copyright::Copyright;
+use crate::dependencies::{self, FetchData};
+use crate::extension_control::ControlSpec;
+use crate::hardening::HardeningPolicy;
+use crate::layout::{Layout, Placement, RuleSet, TemplateContext};
+use crate::lint;
+use crate::manifest::TrunkManifest;
+use crate::mappings::{SonameMappings, UnknownSoname};
+use crate::packages_index::PackagesIndex;
+use crate::preload::ExtensionPreload;
+use crate::split::SubPackage;
+use crate::strip;
+use crate::unarchiver::{Archive, Entry, EntryKind};
+use crate::upgrade_graph::{UpgradeGraph, UpgradeReport};
+use crate::Result;
+use crate::{
+    client::Extension,
+    dependencies::{Dependencies, Relation},
+};
+
+/// Settings that affect the packages we generate
+#[derive(Clone)]
+pub struct BuildOptions {
+    /// When set, the build is reproducible: every timestamp is clamped to this value
+    /// and entries are written in a stable order
+    pub source_date_epoch: Option<u64>,
+    /// Codec used for the `control.tar` and `data.tar` members
+    pub compression: Compression,
+    /// Codec-specific compression level, or the codec's default if unset
+    pub compression_level: Option<u32>,
+    /// Directories the extension's files are installed into
+    pub layout: Arc<Layout>,
+    /// Rules deciding where each file of the Trunk archive goes
+    pub rules: Arc<RuleSet>,
+    /// Package extensions even when nothing is known about their license
+    pub allow_missing_license: bool,
+    /// `Name <email>` of whoever maintains the generated packages
+    pub maintainer: Option<String>,
+    /// The PostgreSQL major version this build targets
+    pub pg_major: u16,
+    /// Move the debug information of shared objects into `-dbgsym` packages
+    pub dbgsym: bool,
+    /// Strip symbol tables and debug info from shared objects
+    pub strip: bool,
+    /// Lint the packages once built, failing on errors
+    pub lint: bool,
+    /// Hardening the shared objects must have, if any is required
+    pub hardening_policy: Option<Arc<HardeningPolicy>>,
+    /// Merge the SQL scripts of older versions into the package
+    pub carry_forward: Option<CarriedScripts>,
+    /// Ship an example conf.d snippet for extensions which have to be preloaded
+    pub preload_snippet: bool,
+    /// The distribution release whose package names dependencies use, if only one is targeted
+    pub distro: Option<String>,
+    /// The packages of the target distribution, which dependencies must resolve against
+    pub packages_index: Option<Arc<PackagesIndex>>,
+    /// The user's packages for sonames the built-in table lacks
+    pub mappings: Option<Arc<SonameMappings>>,
+    /// Append stubs for the sonames whose package is unknown to the mappings file
+    pub write_missing: bool,
+}
+
+impl BuildOptions {
+    /// One set of options for each PostgreSQL version we build for.
+    ///
+    /// Also honors `SOURCE_DATE_EPOCH`, as specified by <https://reproducible-builds.org/specs/source-date-epoch/>
+    pub fn from_args(
+        PackagingArgs {
+            compression,
+            compression_level,
+            layout,
+            rules,
+            allow_missing_license,
+            maintainer,
+            pg_versions: PgVersions(pg_versions),
+            no_dbgsym,
+            strip,
+            lint,
+            hardening_policy,
+            carry_forward,
+            carry_install_scripts,
+            preload_snippet,
+            distro,
+            packages_index,
+            mappings,
+            write_missing,
+        }: PackagingArgs,
+    ) -> Result<Vec<Self>> {
+        let source_date_epoch = std::env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .map(|epoch| {
+                epoch
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid SOURCE_DATE_EPOCH: {epoch}"))
+            })
+            .transpose()?;
+
+        if let Some(level) = compression_level {
+            compression.check_level(level)?;
+        }
+
+        // A `pg_config` layout only describes the installation of a single version
+        let custom_layout = match layout {
+            Some(_) if pg_versions.len() > 1 => {
+                anyhow::bail!(
+                    "--layout can't be used when building for several PostgreSQL versions"
+                )
+            }
+            Some(path) => Some(Arc::new(Layout::from_pg_config(&path)?)),
+            None => None,
+        };
+        let rules = match rules {
+            Some(path) => RuleSet::debian().with_overrides(&path)?,
+            None => RuleSet::debian(),
+        };
+        let rules = Arc::new(rules);
+        let maintainer = maintainer.or_else(Self::maintainer_from_env);
+        let hardening_policy = hardening_policy
+            .map(|path| HardeningPolicy::from_file(&path))
+            .transpose()?
+            .map(Arc::new);
+        let packages_index = if packages_index.is_empty() {
+            None
+        } else {
+            Some(Arc::new(PackagesIndex::from_files(&packages_index)?))
+        };
+        if let Some(distro) = &distro {
+            let known = dependencies::known_distros();
+            if known.contains(&distro.as_str()).not() {
+                anyhow::bail!(
+                    "Unknown --distro {distro}, expected one of {}",
+                    known.join(", ")
+                );
+            }
+        }
+        if write_missing && mappings.is_none() {
+            anyhow::bail!("--write-missing needs a --mappings file to write to");
+        }
+        let mappings = mappings
+            .map(|path| SonameMappings::from_file(&path, write_missing))
+            .transpose()?
+            .map(Arc::new);
+        let carry_forward = match (carry_forward, carry_install_scripts) {
+            (_, true) => Some(CarriedScripts::All),
+            (true, false) => Some(CarriedScripts::Upgrades),
+            (false, false) => None,
+        };
+
+        let all_options = pg_versions
+            .into_iter()
+            .map(|pg_major| Self {
+                source_date_epoch,
+                compression,
+                compression_level,
+                layout: custom_layout
+                    .clone()
+                    .unwrap_or_else(|| Arc::new(Layout::debian(pg_major))),
+                rules: rules.clone(),
+                allow_missing_license,
+                maintainer: maintainer.clone(),
+                pg_major,
+                dbgsym: no_dbgsym.not(),
+                strip,
+                lint,
+                hardening_policy: hardening_policy.clone(),
+                carry_forward,
+                preload_snippet,
+                distro: distro.clone(),
+                packages_index: packages_index.clone(),
+                mappings: mappings.clone(),
+                write_missing,
+            })
+            .collect();
+
+        Ok(all_options)
+    }
+
+    /// The maintainer as `dch` would find it, from `DEBFULLNAME` and `DEBEMAIL`
+    fn maintainer_from_env() -> Option<String> {
+        let email = std::env::var("DEBEMAIL").ok()?;
+        if email.contains('<') {
+            return Some(email);
+        }
+
+        let maintainer = std::env::var("DEBFULLNAME")
+            .map(|name| format!("{name} <{email}>"))
+            .unwrap_or(email);
+
+        Some(maintainer)
+    }
+
+    pub fn is_reproducible(&self) -> bool {
+        self.source_date_epoch.is_some()
+    }
+
+    /// The timestamp to record for a file last modified at `mtime`
+    pub fn clamp_mtime(&self, mtime: u64) -> u64 {
+        match self.source_date_epoch {
+            Some(epoch) => mtime.min(epoch),
+            None => mtime,
+        }
+    }
+
+    /// The timestamp to record for files created by the packager itself
+    pub fn build_time(&self) -> u64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+        self.clamp_mtime(now.as_secs())
+    }
+}
 
 pub struct DebPackage {
     builder: ar::Builder<File>,
+    mtime: u64,
 }
 
 pub struct TarArchive {
     directories_created: HashSet<PathBuf>,
     builder: tar::Builder<Vec<u8>>,
+    options: BuildOptions,
+    /// Timestamp given to the directories we create
+    build_time: u64,
 }
 
 impl TarArchive {
-    pub fn new() -> Self {
+    pub fn new(options: &BuildOptions) -> Self {
         let buf = Vec::new();
         let builder = tar::Builder::new(buf);
 
         Self {
             builder,
             directories_created: HashSet::new(),
+            options: options.clone(),
+            build_time: options.build_time(),
         }
     }
 
+    /// Entries are always owned by root
+    fn normalize_ownership(header: &mut tar::Header) -> Result {
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_username("root")?;
+        header.set_groupname("root")?;
+
+        Ok(())
+    }
+
     fn add_directory(&mut self, path: &Path) -> Result<()> {
         let mut header = tar::Header::new_gnu();
         header.set_entry_type(tar::EntryType::Directory);
         header.set_size(0);
         header.set_mode(0o755);
-        header.set_gid(0);
-        header.set_uid(0);
+        header.set_mtime(self.build_time);
+        Self::normalize_ownership(&mut header)?;
         header.set_cksum();
 
         self.builder
@@ -69,13 +298,57 @@ impl TarArchive {
         Ok(())
     }
 
-    pub fn add_entry<P: AsRef<Path>>(&mut self, entry: &Entry, path: &P) -> Result<()> {
+    /// Add an entry from the source archive with the given permissions, recreating
+    /// directories and symlinks as such
+    pub fn add_entry<P: AsRef<Path>>(&mut self, entry: &Entry, path: &P, mode: u32) -> Result<()> {
         let path = path.as_ref();
         let parent = path.parent().unwrap();
         self.populate_ancestor_paths(parent)?;
 
-        let mut header = entry.tar_header();
-        let contents = Cursor::new(&entry.contents);
+        let mut header = entry.tar_header(self.options.clamp_mtime(entry.mtime), mode);
+        Self::normalize_ownership(&mut header)?;
+
+        match entry.kind {
+            EntryKind::Regular => {
+                header.set_cksum();
+                let contents = Cursor::new(&entry.contents);
+
+                self.builder.append_data(&mut header, path, contents)?;
+            }
+            EntryKind::Directory => {
+                if self.directories_created.insert(path.to_owned()) {
+                    header.set_cksum();
+                    self.builder
+                        .append_data(&mut header, path, std::io::empty())?;
+                }
+            }
+            EntryKind::Symlink => {
+                let target = entry
+                    .link_target
+                    .as_ref()
+                    .with_context(|| format!("Symlink {} has no target", entry.path.display()))?;
+
+                self.builder.append_link(&mut header, path, target)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add a file generated by the packager, such as `control`
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: &P, contents: &[u8]) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            self.populate_ancestor_paths(parent)?;
+        }
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.build_time);
+        Self::normalize_ownership(&mut header)?;
+        header.set_cksum();
 
         self.builder.append_data(&mut header, path, contents)?;
 
@@ -90,19 +363,21 @@ impl TarArchive {
 }
 
 impl DebPackage {
-    pub fn new(path: &Path) -> Result<Self> {
+    pub fn new(path: &Path, options: &BuildOptions) -> Result<Self> {
         let file = File::create(path)?;
         let builder = ar::Builder::new(file);
 
-        Ok(Self { builder })
+        Ok(Self {
+            builder,
+            mtime: options.build_time(),
+        })
     }
 
     pub fn add_file(&mut self, path: impl AsRef<[u8]>, data: &[u8]) -> Result {
         let identifier_bytes = path.as_ref().into();
         let mut header = ar::Header::new(identifier_bytes, data.len() as u64);
         header.set_mode(0o644);
-        // TODO: set modification time
-        header.set_mtime(0);
+        header.set_mtime(self.mtime);
         header.set_uid(0);
         header.set_gid(0);
 
@@ -115,157 +390,842 @@ impl DebPackage {
 pub enum DebPackager {}
 
 impl DebPackager {
-    fn gzip_bytes(bytes: &[u8]) -> Result<Vec<u8>> {
-        let compressed_bytes = {
-            let mut encoder =
-                flate2::write::GzEncoder::new(Vec::with_capacity(2048), Compression::default());
+    /// The Debian package name of an extension built for the given PostgreSQL version,
+    /// e.g. `postgresql-16-pg-cron`, following the naming of Debian's own extension packages
+    pub fn package_name(extension_name: &str, pg_major: u16) -> String {
+        let name = extension_name.to_lowercase().replace('_', "-");
 
-            encoder.write_all(bytes)?;
-            encoder.finish()?
-        };
+        format!("postgresql-{pg_major}-{name}")
+    }
 
-        Ok(compressed_bytes)
+    fn compress(bytes: &[u8], options: &BuildOptions) -> Result<Vec<u8>> {
+        options
+            .compression
+            .compress(bytes, options.compression_level)
     }
 
-    fn gzip_path(path: &Path) -> Result<Vec<u8>> {
-        let uncompressed_bytes = utils::read_to_vec(path)?;
+    /// Return the compressed bytes of a `control` archive holding the given control file
+    fn tar_compress(control_file: &[u8], options: &BuildOptions) -> Result<Vec<u8>> {
+        let mut tar = TarArchive::new(options);
+        tar.add_file(&"control", control_file)?;
 
-        Self::gzip_bytes(&uncompressed_bytes)
+        Self::compress(&tar.into_bytes()?, options)
     }
 
-    /// Return the .tar.gz  bytes of the file on the given path
-    fn tar_gzip(path: &Path) -> Result<Vec<u8>> {
-        let tar_file = tempfile::NamedTempFile::new()?;
+    fn dependencies(
+        sub_package: SubPackage,
+        main_package: &str,
+        extension: &Extension,
+        dependencies: &Dependencies,
+        options: &BuildOptions,
+    ) -> Vec<String> {
+        let mut depends =
+            sub_package.depends(main_package, &extension.latest_version, options.pg_major);
 
-        let mut builder = tar::Builder::new(tar_file.as_file());
-        {
-            let mut control_file = File::open(path)?;
-            builder.append_file("control", control_file.file_mut())?;
-            builder.finish()?;
+        // Only the main package ships shared libraries
+        if sub_package == SubPackage::Main {
+            // Sorted so that the control file does not depend on hashing order
+            depends.extend(dependencies.packages(options.distro.as_deref()));
         }
 
-        Self::gzip_path(tar_file.path())
+        depends
     }
 
-    fn write_dependencies(file: &mut File, dependencies: &Dependencies) -> Result {
-        let suppliers = dependencies.suppliers.values();
-        if suppliers.len() == 0 {
-            return Ok(());
+    /// The packages of the libraries the extension loads with `dlopen`, which only the main
+    /// package asks for
+    fn optional_dependencies(
+        sub_package: SubPackage,
+        dependencies: &Dependencies,
+        relation: Relation,
+        options: &BuildOptions,
+    ) -> Vec<String> {
+        if sub_package != SubPackage::Main {
+            return Vec::new();
         }
-        let last_idx = suppliers.len();
 
-        write!(file, "Depends: ")?;
-
-        // TODO: show dependency versions
-        for (idx, supplier) in suppliers.enumerate() {
-            write!(file, "{}", supplier.name())?;
-            if idx + 1 != last_idx {
-                write!(file, ", ")?;
-            }
-        }
-
-        writeln!(file)?;
-
-        Ok(())
+        dependencies.optional_packages(relation, options.distro.as_deref())
     }
 
-    /// Writes the .deb control file
-    ///
-    /// Docs.:
-    fn write_control_file(extension: &Extension, dependencies: &Dependencies) -> Result<Vec<u8>> {
-        let file_name = format!("{}-{}.control", extension.name, extension.latest_version);
-        let control_path = TEMP_DIR.path().join(&file_name);
-        let mut file = File::create(control_path)?;
-
-        // TODO: save as something else? perhaps "postgres15-{extension-name}-trunk"
-        writeln!(file, "Package: {}", extension.name)?;
-        writeln!(file, "Section: database")?;
-        writeln!(file, "Architecture: amd64")?;
-        writeln!(file, "Version: {}", extension.latest_version)?;
-        writeln!(
-            file,
-            "Description: {}",
-            extension.description.as_deref().unwrap_or("")
-        )?;
-        writeln!(
-            file,
-            "Homepage: https://pgt.dev/extensions/{}",
-            extension.name
-        )?;
-
-        // Write down the dependencies
-        Self::write_dependencies(&mut file, dependencies)?;
-        file.flush()?;
+    /// The control fields of one of the packages the extension is split into
+    fn control_fields(
+        sub_package: SubPackage,
+        main_package: &str,
+        extension: &Extension,
+        dependencies: &Dependencies,
+        spec: Option<&ControlSpec>,
+        architecture: &str,
+        options: &BuildOptions,
+    ) -> Result<ControlFields> {
+        // The registry's description, or else the one of the extension's control file
+        let synopsis = extension
+            .description
+            .as_deref()
+            .or_else(|| spec.and_then(|spec| spec.comment.as_deref()))
+            .unwrap_or("");
+        let synopsis = match sub_package.description_suffix() {
+            Some(suffix) => format!("{synopsis} - {suffix}"),
+            None => synopsis.into(),
+        };
+        let description = match spec {
+            Some(spec) => format!("{synopsis}\n{}", spec.describe()?),
+            None => synopsis,
+        };
+        let is_dbgsym = sub_package == SubPackage::Dbgsym;
 
-        Self::tar_gzip(Path::new(&file_name))
+        Ok(ControlFields {
+            package: sub_package.package_name(main_package),
+            auto_built_package: is_dbgsym.then(|| "debug-symbols".into()),
+            section: Some(sub_package.section().into()),
+            architecture: sub_package.architecture(architecture).into(),
+            version: extension.latest_version.clone(),
+            maintainer: options.maintainer.clone(),
+            description,
+            homepage: Some(format!("https://pgt.dev/extensions/{}", extension.name)),
+            depends: Self::dependencies(
+                sub_package,
+                main_package,
+                extension,
+                dependencies,
+                options,
+            ),
+            recommends: Self::optional_dependencies(
+                sub_package,
+                dependencies,
+                Relation::Recommends,
+                options,
+            ),
+            suggests: Self::optional_dependencies(
+                sub_package,
+                dependencies,
+                Relation::Suggests,
+                options,
+            ),
+            build_ids: Vec::new(),
+            other: Vec::new(),
+        })
     }
 
+    /// Build the packages of an extension: the main one, along with `-dev`, `-doc` and `-jit`
+    /// packages when the install rules place files in them. Returns the paths of the `.deb`s.
     pub async fn build_deb<P: AsRef<Path>>(
         FetchData {
             extension,
             dependencies,
             archive,
+            versions,
+            manifest,
+            history,
         }: FetchData,
         export_dir: P,
-    ) -> Result<PathBuf> {
+        options: &BuildOptions,
+    ) -> Result<Vec<PathBuf>> {
+        let mut archive = archive;
+        let mut dependencies = dependencies;
+        if let Some(mappings) = &options.mappings {
+            dependencies.apply_mappings(mappings);
+        }
         // Check if this .deb is actually writable (e.g. if we know all dependencies it requires)
-        let all_dependencies_are_known = dependencies
-            .suppliers
-            .values()
-            .all(DependencySupplier::is_met);
-        anyhow::ensure!(
-            all_dependencies_are_known,
-            "The packages that supply the dependencies of {} are unknown",
-            extension.name
-        );
+        if dependencies.all_known().not() {
+            Self::report_unknown_sonames(&extension.name, &dependencies, options)?;
+        }
 
-        let archive_path = export_dir.as_ref().join(format!("{}.deb", extension.name));
-        let mut deb_archive = DebPackage::new(&archive_path)?;
-        deb_archive.add_file("debian-binary", b"2.0\n")?;
+        let main_package = Self::package_name(&extension.name, options.pg_major);
+        let architecture = manifest
+            .as_ref()
+            .and_then(TrunkManifest::debian_architecture)
+            .unwrap_or("amd64");
 
-        // Save the `control` file to our temp directory
-        let tar_gzipped = DebPackager::write_control_file(&extension, &dependencies)?;
-        deb_archive.add_file("control.tar.gz", &tar_gzipped)?;
+        if let Some(built_for) = manifest.as_ref().and_then(|manifest| manifest.pg_version) {
+            if built_for != options.pg_major {
+                eprintln!(
+                    "{}: archive was built for PostgreSQL {built_for}, packaging it for {}",
+                    extension.name, options.pg_major
+                );
+            }
+        }
 
-        // Go through each file in the archive and save it to the `deb` folder
-        let tar_gzipped = DebPackager::write_packaged_files(&archive).await?;
-        deb_archive.add_file("data.tar.gz", &tar_gzipped)?;
+        let debug_files = if options.dbgsym {
+            Self::split_debug_info(&extension.name, &mut archive)
+        } else {
+            Vec::new()
+        };
+        // Done after splitting so that the symbol tables still make it into the debug files
+        if options.strip {
+            Self::strip_shared_objects(&extension.name, &mut archive);
+        }
+        if let Some(policy) = &options.hardening_policy {
+            policy.check(
+                &main_package,
+                &extension.latest_version,
+                &archive,
+                export_dir.as_ref(),
+            )?;
+        }
+        if let Some(carried) = options.carry_forward {
+            let count = carry_forward::merge_scripts(
+                &extension.name,
+                &extension.latest_version,
+                &mut archive,
+                &history,
+                carried,
+            );
+            if count > 0 {
+                eprintln!(
+                    "{}: carried {count} script(s) forward from older versions",
+                    extension.name
+                );
+            }
+        }
+        let build_ids: Vec<_> = debug_files
+            .iter()
+            .map(|(build_id, _)| build_id.clone())
+            .collect();
 
-        Ok(archive_path)
-    }
+        let copyright = Copyright::collect(&extension, &archive, options.allow_missing_license)?;
 
-    async fn write_packaged_files(archive: &Archive) -> Result<Vec<u8>> {
-        let mut data_tar = TarArchive::new();
+        let (specs, invalid_control_files) = ControlSpec::from_archive(&archive);
+        for err in invalid_control_files {
+            eprintln!(
+                "{}: {err:#} ({})",
+                extension.name,
+                lint::INVALID_CONTROL_FILE.code
+            );
+        }
+        Self::check_module_pathnames(&extension.name, &specs, &archive);
+        let spec = specs.get(&extension.name).or_else(|| specs.values().next());
+        let upgrades = Self::check_upgrade_paths(&extension.name, &specs, &archive);
+        let upgrades = spec.and_then(|spec| upgrades.get(&spec.name));
+        let preload = ExtensionPreload::from_archive(&extension.name, &archive);
+        let conf_snippet = if options.preload_snippet {
+            Self::conf_snippet(&extension.name, &main_package, &preload, options.pg_major)
+        } else {
+            None
+        };
+
+        let context = TemplateContext {
+            layout: &options.layout,
+            extension: &extension.name,
+            package: &main_package,
+        };
+
+        // The main package is always built, the others only if they get any file
+        let mut packages: BTreeMap<SubPackage, Vec<(&Entry, Placement)>> = BTreeMap::new();
+        packages.insert(SubPackage::Main, Vec::new());
 
         for entry in archive.all_entries() {
-            let maybe_extension = entry.extension();
+            if let Some(mut placement) = options.rules.place(entry, &context)? {
+                Self::honor_script_directory(&mut placement, &specs, &options.layout);
+                packages
+                    .entry(placement.package)
+                    .or_default()
+                    .push((entry, placement));
+            }
+        }
+
+        for (_, entry) in &debug_files {
+            let placement = Placement {
+                target: entry.path.clone(),
+                mode: 0o644,
+                package: SubPackage::Dbgsym,
+            };
+
+            packages
+                .entry(SubPackage::Dbgsym)
+                .or_default()
+                .push((entry, placement));
+        }
+
+        // Checked up front, so that a failed build leaves no .deb behind
+        if let Some(index) = &options.packages_index {
+            for sub_package in packages.keys() {
+                let control = DebPackager::control_fields(
+                    *sub_package,
+                    &main_package,
+                    &extension,
+                    &dependencies,
+                    spec,
+                    architecture,
+                    options,
+                )?;
+                Self::check_against_index(&main_package, &control, index)?;
+            }
+        }
+
+        let mut archives_written = Vec::with_capacity(packages.len());
+
+        for (sub_package, placements) in packages {
+            let package = sub_package.package_name(&main_package);
+            let changelog = Changelog::new(
+                &package,
+                &extension,
+                &versions,
+                options.maintainer.as_deref(),
+                options.build_time(),
+            );
+
+            let archive_path = export_dir.as_ref().join(format!(
+                "{package}_{}_{}.{}",
+                extension.latest_version,
+                sub_package.architecture(architecture),
+                sub_package.file_extension()
+            ));
+            let mut deb_archive = DebPackage::new(&archive_path, options)?;
+            deb_archive.add_file("debian-binary", b"2.0\n")?;
 
-            match maybe_extension {
-                Some(b"control") | Some(b"sql") => {
-                    let target = format!(".//usr/share/postgresql/16/{}", entry.path.display());
+            let extension_suffix = options.compression.extension();
 
-                    data_tar.add_entry(entry, &target)?;
+            let mut control = DebPackager::control_fields(
+                sub_package,
+                &main_package,
+                &extension,
+                &dependencies,
+                spec,
+                architecture,
+                options,
+            )?;
+            if sub_package == SubPackage::Dbgsym {
+                control.build_ids.clone_from(&build_ids);
+            }
+
+            // Only the main package ships SQL scripts and libraries, so it carries what they need
+            let mut generated_files = Vec::new();
+            if sub_package == SubPackage::Main {
+                if let Some(upgrades) =
+                    upgrades.filter(|upgrades| upgrades.upgradable_from.is_empty().not())
+                {
+                    control.other.push((
+                        "X-Postgres-Upgradable-From".into(),
+                        upgrades.upgradable_from.join(", "),
+                    ));
                 }
-                Some(b"json") => {
-                    // TODO: I don't know if these should go somewhere
+                if let Some(value) = preload.control_field() {
+                    control
+                        .other
+                        .push(("X-Postgres-Preload".into(), value.into()));
+                    control.other.push((
+                        "X-Postgres-Preload-Libraries".into(),
+                        preload.libraries.join(", "),
+                    ));
                 }
-                Some(b"so") => {
-                    let target = format!(".//usr/lib/postgresql/16/lib/{}", entry.path.display());
+                generated_files.extend(conf_snippet.iter().cloned());
+            }
 
-                    data_tar.add_entry(entry, &target)?;
-                }
-                Some(b"bc") => {
-                    let target = format!(".//usr/lib/postgresql/16/lib/{}", entry.path.display());
+            let control_tar = Self::tar_compress(control.render()?.as_bytes(), options)?;
+            deb_archive.add_file(format!("control.tar{extension_suffix}"), &control_tar)?;
 
-                    data_tar.add_entry(entry, &target)?;
-                }
-                Some(_) | None => {
-                    // If the file had no extension, or some other, then it's likely a license file
+            // Go through each file placed in this package and save it to the `deb` folder
+            let data_tar = DebPackager::write_packaged_files(
+                &package,
+                placements,
+                &generated_files,
+                &copyright,
+                &changelog,
+                options,
+            )
+            .await?;
+            deb_archive.add_file(format!("data.tar{extension_suffix}"), &data_tar)?;
+
+            archives_written.push(archive_path);
+        }
+
+        Ok(archives_written)
+    }
+
+    /// Fail, listing the sonames whose package is unknown along with suggestions, after
+    /// appending stubs for them to the mappings file if asked to
+    fn report_unknown_sonames(
+        extension_name: &str,
+        dependencies: &Dependencies,
+        options: &BuildOptions,
+    ) -> Result {
+        let mappings = options.mappings.as_deref();
+        let unknown: Vec<_> = dependencies
+            .unknown_sonames()
+            .into_iter()
+            .map(|soname| UnknownSoname::new(soname, mappings, options.packages_index.as_deref()))
+            .collect();
+
+        if let Some(mappings) = mappings.filter(|_| options.write_missing) {
+            mappings.append_stubs(extension_name, &unknown)?;
+        }
+
+        let unknown: Vec<_> = unknown
+            .iter()
+            .map(|unknown| format!("  {unknown}"))
+            .collect();
+        anyhow::bail!(
+            "The packages that supply the dependencies of {extension_name} are unknown:\n{}",
+            unknown.join("\n")
+        )
+    }
+
+    /// Fail unless every `Depends` relation resolves against the distribution's index.
+    /// Unresolvable `Recommends` and `Suggests` are only reported, as apt installs without them.
+    fn check_against_index(
+        main_package: &str,
+        control: &ControlFields,
+        index: &PackagesIndex,
+    ) -> Result {
+        let package = &control.package;
+        // The packages built from the extension aren't in the index
+        let unresolved = |relations: &[String]| -> Vec<String> {
+            relations
+                .iter()
+                .filter(|relation| relation.split_whitespace().next() != Some(main_package))
+                .filter_map(|relation| {
+                    index
+                        .check(relation)
+                        .err()
+                        .map(|reason| format!("  {relation}: {reason}"))
+                })
+                .collect()
+        };
+
+        for (field, relations) in [
+            ("Recommends", &control.recommends),
+            ("Suggests", &control.suggests),
+        ] {
+            for line in unresolved(relations) {
+                eprintln!(
+                    "{package}: {field} does not resolve against the packages index:\n{line}"
+                );
+            }
+        }
+
+        let depends = unresolved(&control.depends);
+        anyhow::ensure!(
+            depends.is_empty(),
+            "{package}: Depends does not resolve against the packages index:\n{}",
+            depends.join("\n")
+        );
+
+        Ok(())
+    }
+
+    /// Warn about control files whose `module_pathname` names a library the archive doesn't hold
+    fn check_module_pathnames(
+        extension_name: &str,
+        specs: &BTreeMap<String, ControlSpec>,
+        archive: &Archive,
+    ) {
+        for spec in specs.values() {
+            let Some(library) = spec.module_library() else {
+                continue;
+            };
+
+            let file_name = format!("{library}.so");
+            let is_shipped = archive.shared_objects().any(|entry| {
+                entry
+                    .path
+                    .file_name()
+                    .is_some_and(|name| *name == *file_name)
+            });
+
+            if is_shipped.not() {
+                eprintln!(
+                    "{extension_name}: {}.control loads {}, but the archive has no {file_name}",
+                    spec.name,
+                    spec.module_pathname.as_deref().unwrap_or_default()
+                );
+            }
+        }
+    }
+
+    /// Work out which versions each extension's SQL scripts can update in place, warning about
+    /// versions that can't be installed or updated to the `default_version`
+    fn check_upgrade_paths(
+        extension_name: &str,
+        specs: &BTreeMap<String, ControlSpec>,
+        archive: &Archive,
+    ) -> BTreeMap<String, UpgradeReport> {
+        let mut reports = BTreeMap::new();
+
+        for spec in specs.values() {
+            let Some(default_version) = &spec.default_version else {
+                continue;
+            };
+
+            let report = UpgradeGraph::from_archive(archive, &spec.name)
+                .analyze(&spec.name, default_version);
+            for problem in report.problems() {
+                eprintln!("{extension_name}: {problem}");
+            }
+
+            reports.insert(spec.name.clone(), report);
+        }
+
+        reports
+    }
+
+    /// The path and contents of the example conf.d snippet of an extension which has to be
+    /// preloaded. It's documentation: enabling it is left to the administrator of each cluster.
+    fn conf_snippet(
+        extension_name: &str,
+        main_package: &str,
+        preload: &ExtensionPreload,
+        pg_major: u16,
+    ) -> Option<(String, String)> {
+        let snippet = preload.conf_snippet(extension_name, pg_major)?;
+
+        Some((
+            format!("/usr/share/doc/{main_package}/examples/{extension_name}.conf"),
+            snippet,
+        ))
+    }
+
+    /// Move the SQL scripts, and secondary control files, of extensions whose control file sets
+    /// `directory` from `{sharedir}/extension` to that directory
+    fn honor_script_directory(
+        placement: &mut Placement,
+        specs: &BTreeMap<String, ControlSpec>,
+        layout: &Layout,
+    ) {
+        let default_directory = Path::new(&layout.sharedir).join("extension");
+        if placement.target.parent() != Some(default_directory.as_path()) {
+            return;
+        }
+
+        let Some(file_name) = placement.target.file_name().and_then(|name| name.to_str()) else {
+            return;
+        };
+        let is_script = file_name.ends_with(".sql")
+            || (file_name.ends_with(".control") && file_name.contains("--"));
+        let extension_name = file_name.split("--").next().unwrap_or_default();
+
+        let Some(spec) = specs
+            .get(extension_name)
+            .filter(|spec| spec.directory.is_some())
+        else {
+            return;
+        };
+        if is_script {
+            placement.target = spec.script_directory(&layout.sharedir).join(file_name);
+        }
+    }
+
+    /// Strip the debug information out of every shared object of the archive. Returns the
+    /// build-id and detached debug file of each, once per build-id, as hard links are copies.
+    fn split_debug_info(extension_name: &str, archive: &mut Archive) -> Vec<(String, Entry)> {
+        let mut debug_files = Vec::new();
+        let mut build_ids = HashSet::new();
+
+        for entry in archive.shared_objects_mut() {
+            let split = match strip::split_debug_info(&entry.contents) {
+                Result::Ok(Some(split)) => split,
+                Result::Ok(None) => continue,
+                Err(err) => {
+                    eprintln!(
+                        "{extension_name}: keeping the debug info of {}: {err:#}",
+                        entry.path.display()
+                    );
+                    continue;
                 }
+            };
+
+            let debug_file = Entry {
+                path: split.debug_path(),
+                contents: split.debug_file,
+                mtime: entry.mtime,
+                kind: EntryKind::Regular,
+                mode: 0o644,
+                link_target: None,
+            };
+            entry.contents = split.stripped;
+
+            if build_ids.insert(split.build_id.clone()) {
+                debug_files.push((split.build_id, debug_file));
             }
         }
 
+        debug_files
+    }
+
+    /// Strip every shared object of the archive, leaving those which can't be stripped as they are
+    fn strip_shared_objects(extension_name: &str, archive: &mut Archive) {
+        for entry in archive.shared_objects_mut() {
+            match strip::strip(&entry.contents) {
+                Result::Ok(stripped) => entry.contents = stripped,
+                Err(err) => eprintln!(
+                    "{extension_name}: not stripping {}: {err:#}",
+                    entry.path.display()
+                ),
+            }
+        }
+    }
+
+    async fn write_packaged_files(
+        package: &str,
+        mut placements: Vec<(&Entry, Placement)>,
+        generated_files: &[(String, String)],
+        copyright: &Copyright<'_>,
+        changelog: &Changelog<'_>,
+        options: &BuildOptions,
+    ) -> Result<Vec<u8>> {
+        let mut data_tar = TarArchive::new(options);
+
+        if options.is_reproducible() {
+            placements.sort_unstable_by(|(_, a), (_, b)| a.target.cmp(&b.target));
+        }
+
+        for (entry, placement) in placements {
+            let target = Path::new(".").join(placement.target.strip_prefix("/")?);
+
+            data_tar.add_entry(entry, &target, placement.mode)?;
+        }
+
+        for (path, contents) in generated_files {
+            data_tar.add_file(&format!(".{path}"), contents.as_bytes())?;
+        }
+
+        // Every package ships its own copyright and changelog
+        let doc_dir = format!("./usr/share/doc/{package}");
+        data_tar.add_file(
+            &format!("{doc_dir}/copyright"),
+            copyright.render()?.as_bytes(),
+        )?;
+        data_tar.add_file(
+            &format!("{doc_dir}/changelog.Debian.gz"),
+            &changelog.to_gzip()?,
+        )?;
+
         let bytes = data_tar.into_bytes()?;
-        Self::gzip_bytes(&bytes)
+        Self::compress(&bytes, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ops::Not, path::Path, sync::Arc};
+
+    use crate::{
+        client::Extension,
+        compression::Compression,
+        deb_reader::{DataEntryKind, DebFile},
+        dependencies::{Dependencies, FetchData},
+        layout::{Layout, RuleSet},
+        unarchiver::{Entry, EntryKind},
+    };
+
+    use super::{BuildOptions, DebPackager, TarArchive};
+
+    /// A Trunk archive of `myext` 1.0, whose files were all modified at `mtime`
+    fn fetch_data(mtime: u64) -> FetchData {
+        let files: [(&str, &[u8]); 5] = [
+            (
+                "extension/myext.control",
+                b"default_version = '1.0'\nrelocatable = true\n",
+            ),
+            ("extension/myext--1.0.sql", b"SELECT 1;\n"),
+            ("include/server/extension/myext/myext.h", b"#pragma once\n"),
+            ("README.md", b"# myext\n"),
+            ("licenses/LICENSE", b"MIT License\n"),
+        ];
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(mtime);
+            builder.append_data(&mut header, path, contents).unwrap();
+        }
+        // An empty directory, which has to be kept as well
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o755);
+        header.set_mtime(mtime);
+        builder
+            .append_data(
+                &mut header,
+                "include/server/extension/myext/private/",
+                std::io::empty(),
+            )
+            .unwrap();
+        let tar = builder.into_inner().unwrap();
+        let tar_gz = Compression::Gzip.compress(&tar, None).unwrap();
+
+        let extension = Extension {
+            name: "myext".into(),
+            license: Some("MIT".into()),
+            latest_version: "1.0".into(),
+            description: Some("My extension".into()),
+        };
+
+        Dependencies::decompress_archive(extension, Vec::new(), &tar_gz).unwrap()
+    }
+
+    fn options(source_date_epoch: Option<u64>) -> BuildOptions {
+        BuildOptions {
+            source_date_epoch,
+            compression: Compression::Gzip,
+            compression_level: None,
+            layout: Arc::new(Layout::debian(16)),
+            rules: Arc::new(RuleSet::debian()),
+            allow_missing_license: false,
+            maintainer: Some("Jane Doe <jane@example.com>".into()),
+            pg_major: 16,
+            dbgsym: true,
+            strip: false,
+            lint: false,
+            hardening_policy: None,
+            carry_forward: None,
+            preload_snippet: false,
+            distro: None,
+            packages_index: None,
+            mappings: None,
+            write_missing: false,
+        }
+    }
+
+    async fn build(export_dir: &Path, options: &BuildOptions) -> Vec<(String, Vec<u8>)> {
+        let paths = DebPackager::build_deb(fetch_data(1_700_000_000), export_dir, options)
+            .await
+            .unwrap();
+
+        paths
+            .into_iter()
+            .map(|path| {
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                (name, fs_err::read(&path).unwrap())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reproducible_with_source_date_epoch() {
+        let options = options(Some(1_600_000_000));
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+
+        let first = build(first.path(), &options).await;
+        // Whole seconds are recorded, so this is enough for the build time to differ
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let second = build(second.path(), &options).await;
+
+        let names: Vec<_> = first.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "postgresql-16-myext_1.0_amd64.deb",
+                "postgresql-16-myext-dev_1.0_amd64.deb",
+                "postgresql-16-myext-doc_1.0_all.deb",
+            ]
+        );
+        assert!(first == second, "the packages differ between builds");
+    }
+
+    #[tokio::test]
+    async fn read_back() {
+        let options = BuildOptions {
+            compression: Compression::None,
+            ..options(Some(1_600_000_000))
+        };
+        let export_dir = tempfile::tempdir().unwrap();
+        let paths = DebPackager::build_deb(fetch_data(1_500_000_000), export_dir.path(), &options)
+            .await
+            .unwrap();
+
+        let main = DebFile::read(&paths[0]).unwrap();
+        assert_eq!(main.format_version, "2.0");
+        assert_eq!(main.control_compression, Compression::None);
+        assert_eq!(main.data_compression, Compression::None);
+        assert_eq!(main.control_files, ["control"]);
+        assert_eq!(main.control.package, "postgresql-16-myext");
+        assert_eq!(main.control.version, "1.0");
+        assert_eq!(main.control.architecture, "amd64");
+        assert_eq!(
+            main.control.maintainer.as_deref(),
+            Some("Jane Doe <jane@example.com>")
+        );
+
+        let script = main
+            .entries
+            .iter()
+            .find(|entry| entry.path == "./usr/share/postgresql/16/extension/myext--1.0.sql")
+            .expect("the install script is packaged");
+        assert_eq!(script.kind, DataEntryKind::File);
+        assert_eq!(script.contents, b"SELECT 1;\n");
+        assert_eq!(script.mode, 0o644);
+        assert_eq!(
+            (script.owner.as_str(), script.group.as_str()),
+            ("root", "root")
+        );
+        // Older than SOURCE_DATE_EPOCH, so kept as is
+        assert_eq!(script.mtime, 1_500_000_000);
+        assert!(main
+            .entries
+            .iter()
+            .any(|entry| entry.path == "./usr/share/doc/postgresql-16-myext/copyright"));
+        assert!(main
+            .entries
+            .iter()
+            .all(|entry| entry.path.starts_with("./usr/include").not()));
+
+        let dev = DebFile::read(&paths[1]).unwrap();
+        assert_eq!(dev.control.package, "postgresql-16-myext-dev");
+        assert_eq!(dev.control.depends[0], "postgresql-16-myext (= 1.0)");
+        let header = dev
+            .entries
+            .iter()
+            .find(|entry| entry.path.ends_with("/myext.h"))
+            .expect("the header is packaged");
+        assert_eq!(
+            header.path,
+            "./usr/include/postgresql/16/server/extension/myext/myext.h"
+        );
+        assert_eq!(header.contents, b"#pragma once\n");
+        let empty = dev
+            .entries
+            .iter()
+            .find(|entry| entry.path.contains("/myext/private"))
+            .expect("the empty directory is packaged");
+        assert_eq!(empty.kind, DataEntryKind::Directory);
+        assert_eq!(empty.mode, 0o755);
+
+        let doc = DebFile::read(&paths[2]).unwrap();
+        assert_eq!(doc.control.architecture, "all");
+        assert!(doc
+            .entries
+            .iter()
+            .any(|entry| entry.path == "./usr/share/doc/postgresql-16-myext-doc/README.md"));
+    }
+
+    #[test]
+    fn directories_added_once() {
+        let directory = Entry {
+            path: "include/server/extension/myext/private".into(),
+            contents: Vec::new(),
+            mtime: 1_500_000_000,
+            kind: EntryKind::Directory,
+            mode: 0o755,
+            link_target: None,
+        };
+        let target = Path::new("./usr/include/private");
+
+        let mut tar = TarArchive::new(&options(None));
+        tar.add_entry(&directory, &target, 0o750).unwrap();
+        tar.add_entry(&directory, &target, 0o750).unwrap();
+        let bytes = tar.into_bytes().unwrap();
+
+        let mut archive = tar::Archive::new(bytes.as_slice());
+        let entries: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let path = entry.path().unwrap().display().to_string();
+                (path, entry.header().mode().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("./".to_owned(), 0o755),
+                ("./usr".to_owned(), 0o755),
+                ("./usr/include".to_owned(), 0o755),
+                ("./usr/include/private".to_owned(), 0o750),
+            ]
+        );
     }
 }
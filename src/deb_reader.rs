@@ -0,0 +1,253 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    io::{Cursor, Read},
+    ops::Not,
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+use tar::EntryType;
+
+use crate::{
+    compression::Compression, control::ControlFields, hardening::Hardening, utils, Result,
+};
+
+/// What a data entry of a .deb is
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DataEntryKind {
+    File,
+    Directory,
+    Symlink,
+    HardLink,
+    Other,
+}
+
+/// A file, directory or link installed by a .deb
+#[derive(Serialize, Debug)]
+pub struct DataEntry {
+    /// The path as stored in `data.tar`, e.g. `./usr/lib/postgresql/16/lib/foo.so`
+    pub path: String,
+    pub kind: DataEntryKind,
+    pub mode: u32,
+    pub size: u64,
+    pub mtime: u64,
+    pub owner: String,
+    pub group: String,
+    pub link_target: Option<String>,
+    #[serde(skip)]
+    pub contents: Vec<u8>,
+}
+
+impl DataEntry {
+    /// Whether this is an ELF file named like a shared object
+    pub fn is_shared_object(&self) -> bool {
+        let file_name = self.path.rsplit('/').next().unwrap_or_default();
+
+        self.kind == DataEntryKind::File
+            && utils::is_shared_object_name(file_name)
+            && self.contents.starts_with(b"\x7fELF")
+    }
+
+    /// The permissions as `ls -l` shows them, e.g. `-rw-r--r--`
+    pub fn permissions(&self) -> String {
+        let kind = match self.kind {
+            DataEntryKind::Directory => 'd',
+            DataEntryKind::Symlink => 'l',
+            DataEntryKind::HardLink => 'h',
+            DataEntryKind::File | DataEntryKind::Other => '-',
+        };
+
+        let mut permissions = String::with_capacity(10);
+        permissions.push(kind);
+        for shift in [6, 3, 0] {
+            let bits = (self.mode >> shift) & 0o7;
+            permissions.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+            permissions.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+            permissions.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+        }
+
+        permissions
+    }
+}
+
+/// A .deb, as read back from disk
+#[derive(Serialize, Debug)]
+pub struct DebFile {
+    /// The contents of `debian-binary`, e.g. `2.0`
+    pub format_version: String,
+    pub control_compression: Compression,
+    pub data_compression: Compression,
+    pub control: ControlFields,
+    /// The names of the files in `control.tar`
+    pub control_files: Vec<String>,
+    pub entries: Vec<DataEntry>,
+    /// The hardening of every shared object, by path
+    pub hardening: BTreeMap<String, Hardening>,
+}
+
+impl DebFile {
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = utils::read_to_vec(path)?;
+
+        Self::from_bytes(&bytes).with_context(|| format!("Failed to read {}", path.display()))
+    }
+
+    /// Parse a .deb: an ar archive holding `debian-binary`, `control.tar*` and `data.tar*`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut archive = ar::Archive::new(bytes);
+        let mut format_version = None;
+        let mut control_tar = None;
+        let mut data_tar = None;
+
+        while let Some(member) = archive.next_entry() {
+            let mut member = member?;
+            let name = String::from_utf8_lossy(member.header().identifier()).into_owned();
+            let mut contents = Vec::with_capacity(member.header().size() as usize);
+            member.read_to_end(&mut contents)?;
+
+            if name == "debian-binary" {
+                format_version = Some(String::from_utf8_lossy(&contents).trim().to_owned());
+            } else if name.starts_with("control.tar") {
+                let compression = Compression::from_member_name(&name)
+                    .with_context(|| format!("Unknown compression of {name}"))?;
+                control_tar = Some((compression, contents));
+            } else if name.starts_with("data.tar") {
+                let compression = Compression::from_member_name(&name)
+                    .with_context(|| format!("Unknown compression of {name}"))?;
+                data_tar = Some((compression, contents));
+            }
+        }
+
+        let format_version = format_version.context("Missing debian-binary member")?;
+        let (control_compression, control_tar) = control_tar.context("Missing control.tar")?;
+        let (data_compression, data_tar) = data_tar.context("Missing data.tar")?;
+
+        let control_entries = Self::read_tar(&control_compression.decompress(&control_tar)?)?;
+        let control_files: Vec<_> = control_entries
+            .iter()
+            .filter(|entry| entry.kind == DataEntryKind::File)
+            .map(|entry| entry.path.trim_start_matches("./").to_owned())
+            .collect();
+
+        let Some(control) = control_entries
+            .iter()
+            .find(|entry| entry.path.trim_start_matches("./") == "control")
+        else {
+            bail!("control.tar has no control file");
+        };
+        let control = ControlFields::parse(&String::from_utf8_lossy(&control.contents))?;
+
+        let entries = Self::read_tar(&data_compression.decompress(&data_tar)?)?;
+        let hardening = entries
+            .iter()
+            .filter(|entry| entry.is_shared_object())
+            .filter_map(|entry| {
+                let hardening = Hardening::analyze(&entry.contents).ok()?;
+                Some((entry.path.clone(), hardening))
+            })
+            .collect();
+
+        Ok(Self {
+            format_version,
+            control_compression,
+            data_compression,
+            control,
+            control_files,
+            entries,
+            hardening,
+        })
+    }
+
+    fn read_tar(tar: &[u8]) -> Result<Vec<DataEntry>> {
+        let mut archive = tar::Archive::new(Cursor::new(tar));
+        let mut entries = Vec::new();
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let header = entry.header();
+
+            let kind = match header.entry_type() {
+                EntryType::Regular | EntryType::Continuous => DataEntryKind::File,
+                EntryType::Directory => DataEntryKind::Directory,
+                EntryType::Symlink => DataEntryKind::Symlink,
+                EntryType::Link => DataEntryKind::HardLink,
+                _ => DataEntryKind::Other,
+            };
+            let path = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+            let link_target = entry
+                .link_name()?
+                .map(|target| target.to_string_lossy().into_owned());
+
+            let header = entry.header();
+            let mode = header.mode()?;
+            let size = header.size()?;
+            let mtime = header.mtime()?;
+            let owner = header.username().ok().flatten().unwrap_or("").to_owned();
+            let group = header.groupname().ok().flatten().unwrap_or("").to_owned();
+
+            let mut contents = Vec::with_capacity(size as usize);
+            entry.read_to_end(&mut contents)?;
+
+            entries.push(DataEntry {
+                path,
+                kind,
+                mode,
+                size,
+                mtime,
+                owner,
+                group,
+                link_target,
+                contents,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// A human-readable summary, in the spirit of `dpkg-deb --info --contents`
+    pub fn describe(&self) -> Result<String> {
+        let mut description = String::with_capacity(4096);
+
+        writeln!(
+            description,
+            "format {}, control.tar: {}, data.tar: {}",
+            self.format_version, self.control_compression, self.data_compression
+        )?;
+        writeln!(
+            description,
+            "control files: {}",
+            self.control_files.join(", ")
+        )?;
+        writeln!(description)?;
+        description.push_str(&self.control.render()?);
+        writeln!(description)?;
+
+        for entry in &self.entries {
+            write!(
+                description,
+                "{} {}/{} {:>10} {}",
+                entry.permissions(),
+                entry.owner,
+                entry.group,
+                entry.size,
+                entry.path
+            )?;
+            if let Some(target) = &entry.link_target {
+                write!(description, " -> {target}")?;
+            }
+            writeln!(description)?;
+        }
+
+        if self.hardening.is_empty().not() {
+            writeln!(description)?;
+            for (path, hardening) in &self.hardening {
+                writeln!(description, "{path}: {hardening}")?;
+            }
+        }
+
+        Ok(description)
+    }
+}
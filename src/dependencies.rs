@@ -1,17 +1,23 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::Display,
+    ops::Not,
     sync::Arc,
 };
 
 use owo_colors::OwoColorize;
 use phf::{phf_map, phf_set, Map};
+use serde::Serialize;
 
 use crate::{
-    client::{Client, Extension},
+    client::{Client, Extension, ExtensionVersion},
+    dependency_graph::ObjectDependencies,
+    extension_control::ControlSpec,
+    manifest::TrunkManifest,
+    mappings::SonameMappings,
     unarchiver::Archive,
 };
-use crate::{unarchiver::Unarchiver, Result};
+use crate::{unarchiver::Unarchiver, utils, Result};
 
 /// Shared libraries supplied by libc
 static BASIC_SHARED_LIBS: phf::Set<&'static str> = phf_set! {
@@ -24,17 +30,17 @@ static DEPENDENCY_SUPPLIERS: Map<&'static str, &'static str> = phf_map! {
     "libc.so.6" => "libc6",
     "libstdc++.so.6" => "libstdc++6",
     "libR.so" => "r-base-core",
-    "libcrypto.so.3" => "openssl",
+    "libcrypto.so.3" => "libssl3 | libssl3t64",
     "liblz4.so.1" => "liblz4-1",
-    "libgeos_c.so.1" => "libgeos-c1v5",
-    "libtcl8.6.so" => "libtcl8.6.so",
+    "libgeos_c.so.1" => "libgeos-c1v5 | libgeos-c1t64",
+    "libtcl8.6.so" => "libtcl8.6",
     "libpcre2-8.so.0" => "libpcre2-8-0",
     "libhiredis.so.0.14" => "libhiredis0.14",
-    "libuuid.so.1" => "libuuid1",
+    "libuuid.so.1" => "libuuid1 | libuuid1t64",
     "libgroonga.so.0" => "libgroonga0",
     "libopenblas.so.0" => "libopenblas0-pthread",
-    "libcurl.so.4" => "libcurl4",
-    "libpython3.10.so.1." => "libpython3.10",
+    "libcurl.so.4" => "libcurl4 | libcurl4t64",
+    "libpython3.10.so.1.0" => "libpython3.10",
     "libjson-c.so.5" => "libjson-c5",
     "libsybdb.so.5" => "libsybdb5",
     "libsodium.so.23" => "libsodium23",
@@ -53,26 +59,205 @@ static DEPENDENCY_SUPPLIERS: Map<&'static str, &'static str> = phf_map! {
     "libz.so.1" => "zlib1g",
     "libperl.so.5.34" => "libperl5.34",
     "libgomp.so.1" => "libgomp1",
-    "libssl.so.3" => "libssl3",
+    "libssl.so.3" => "libssl3 | libssl3t64",
     "libproj.so.22" => "libproj22",
     "libSFCGAL.so.1" => "libsfcgal1",
 };
 
-#[derive(Hash, Clone, Copy)]
+/// Every soname whose package we know, along with the relation naming it
+pub fn known_suppliers() -> impl Iterator<Item = (&'static str, &'static str)> {
+    DEPENDENCY_SUPPLIERS
+        .entries()
+        .map(|(soname, relation)| (*soname, *relation))
+}
+
+/// Packages renamed in a given release of a distribution, by codename, then by soname.
+/// Where both names are valid somewhere, the default table lists them as alternatives.
+static DISTRO_OVERRIDES: Map<&'static str, Map<&'static str, &'static str>> = phf_map! {
+    // The 64-bit time_t transition added a `t64` suffix to these
+    "noble" => phf_map! {
+        "libssl.so.3" => "libssl3t64",
+        "libcrypto.so.3" => "libssl3t64",
+        "libcurl.so.4" => "libcurl4t64",
+        "libuuid.so.1" => "libuuid1t64",
+        "libgeos_c.so.1" => "libgeos-c1t64",
+    },
+    "jammy" => phf_map! {
+        "libssl.so.3" => "libssl3",
+        "libcrypto.so.3" => "libssl3",
+        "libcurl.so.4" => "libcurl4",
+        "libuuid.so.1" => "libuuid1",
+        "libgeos_c.so.1" => "libgeos-c1v5",
+    },
+};
+
+/// The codenames of the distribution releases whose package names we know
+pub fn known_distros() -> Vec<&'static str> {
+    let mut distros: Vec<_> = DISTRO_OVERRIDES.keys().copied().collect();
+    distros.sort_unstable();
+
+    distros
+}
+
+/// Whether `soname` is one of the libraries of libc, which every package may take for granted
+pub fn is_libc_soname(soname: &str) -> bool {
+    soname == "libc.so.6" || BASIC_SHARED_LIBS.contains(soname)
+}
+
+/// One of the packages that can satisfy a dependency, with an optional version constraint
+#[derive(Serialize, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Alternative {
+    pub package: String,
+    /// e.g. `>= 2.34`
+    pub version: Option<String>,
+}
+
+impl Display for Alternative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{} ({version})", self.package),
+            None => f.write_str(&self.package),
+        }
+    }
+}
+
+#[derive(Serialize, Hash, Clone, PartialEq, Eq, Debug)]
+#[serde(untagged)]
 pub enum DependencySupplier {
-    MetBy { package: &'static str },
+    /// Any one of these packages supplies the library
+    MetBy {
+        alternatives: Vec<Alternative>,
+    },
     Unknown,
 }
 
 impl DependencySupplier {
     pub fn is_met(&self) -> bool {
-        matches!(self, Self::MetBy { package: _ })
+        matches!(self, Self::MetBy { alternatives: _ })
     }
 
-    pub fn name(&self) -> &str {
+    /// The dependency in Debian's relationship syntax, e.g. `libssl3 | libssl3t64`
+    pub fn relation(&self) -> String {
         match self {
-            DependencySupplier::MetBy { package } => package,
-            DependencySupplier::Unknown => "<unknown>",
+            DependencySupplier::MetBy { alternatives } => {
+                let alternatives: Vec<_> =
+                    alternatives.iter().map(Alternative::to_string).collect();
+                alternatives.join(" | ")
+            }
+            DependencySupplier::Unknown => "<unknown>".into(),
+        }
+    }
+
+    /// The names of the packages which can supply the dependency
+    pub fn packages(&self) -> impl Iterator<Item = &str> {
+        let alternatives = match self {
+            DependencySupplier::MetBy { alternatives } => alternatives.as_slice(),
+            DependencySupplier::Unknown => &[],
+        };
+
+        alternatives
+            .iter()
+            .map(|alternative| alternative.package.as_str())
+    }
+
+    /// Parse a relation such as `libfoo1 (>= 1.2) | libfoo1t64`
+    pub fn parse(relation: &str) -> Self {
+        let alternatives = relation
+            .split('|')
+            .map(str::trim)
+            .filter(|alternative| alternative.is_empty().not())
+            .map(|alternative| match alternative.split_once('(') {
+                Some((package, version)) => Alternative {
+                    package: package.trim().to_owned(),
+                    version: Some(version.trim_end_matches(')').trim().to_owned()),
+                },
+                None => Alternative {
+                    package: alternative.to_owned(),
+                    version: None,
+                },
+            })
+            .collect();
+
+        DependencySupplier::MetBy { alternatives }
+    }
+
+    /// Like [`DependencySupplier::for_soname`], but counting the libraries of libc as `libc.so.6`
+    pub fn resolve(soname: &str) -> Self {
+        if BASIC_SHARED_LIBS.contains(soname) {
+            Self::for_soname("libc.so.6")
+        } else {
+            Self::for_soname(soname)
+        }
+    }
+
+    /// How to ask for a library loaded with `dlopen`: recommended if we know who supplies that
+    /// very soname, suggested if we only know who supplies some version of it
+    pub fn for_dlopened(soname: &str) -> (Relation, Self) {
+        match Self::for_soname(soname) {
+            DependencySupplier::Unknown => {
+                (Relation::Suggests, Self::for_unversioned_soname(soname))
+            }
+            supplier => (Relation::Recommends, supplier),
+        }
+    }
+
+    /// The packages supplying `soname`, if we know them
+    pub fn for_soname(soname: &str) -> Self {
+        DEPENDENCY_SUPPLIERS
+            .get(soname)
+            .map(|relation| Self::parse(relation))
+            .unwrap_or(DependencySupplier::Unknown)
+    }
+
+    /// The packages supplying some version of `soname`, e.g. `libfoo.so.1` for `libfoo.so`
+    fn for_unversioned_soname(soname: &str) -> Self {
+        let prefix = format!("{soname}.");
+
+        DEPENDENCY_SUPPLIERS
+            .entries()
+            .filter(|(library, _)| library.starts_with(&prefix))
+            .min_by_key(|(library, _)| *library)
+            .map(|(_, relation)| Self::parse(relation))
+            .unwrap_or(DependencySupplier::Unknown)
+    }
+
+    /// The package `distro` ships `soname` in, if it's renamed there
+    fn distro_override(distro: &str, soname: &str) -> Option<Self> {
+        let relation = DISTRO_OVERRIDES.get(distro)?.get(soname)?;
+
+        Some(Self::parse(relation))
+    }
+
+    /// Require at least `version` of every alternative which has no constraint yet
+    fn at_least(mut self, version: &str) -> Self {
+        if let DependencySupplier::MetBy { alternatives } = &mut self {
+            for alternative in alternatives
+                .iter_mut()
+                .filter(|alternative| alternative.version.is_none())
+            {
+                alternative.version = Some(format!(">= {version}"));
+            }
+        }
+
+        self
+    }
+}
+
+/// How strongly a package asks for a library it loads with `dlopen`
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Relation {
+    /// The exact library is named, so the code very likely uses it
+    Recommends,
+    /// Only an unversioned name is, so the code is probably probing for it
+    Suggests,
+}
+
+impl Display for Relation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Relation::Recommends => f.write_str("recommended"),
+            Relation::Suggests => f.write_str("suggested"),
         }
     }
 }
@@ -80,7 +265,7 @@ impl DependencySupplier {
 impl Display for DependencySupplier {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            DependencySupplier::MetBy { package } => write!(f, "{}", package.green()),
+            DependencySupplier::MetBy { .. } => write!(f, "{}", self.relation().green()),
             DependencySupplier::Unknown => write!(f, "{}", "(unknown)".red()),
         }
     }
@@ -89,17 +274,14 @@ impl Display for DependencySupplier {
 pub struct Dependencies {
     pub shared_libraries: HashSet<Arc<str>>,
     pub suppliers: HashMap<Arc<str>, DependencySupplier>,
-}
-
-impl Display for Dependencies {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for library in &self.shared_libraries {
-            let supplier = &self.suppliers[library];
-            writeln!(f, "\t{library} met by {supplier}")?
-        }
-
-        Ok(())
-    }
+    /// Packages the Trunk manifest declared, which take precedence over the suppliers we guess
+    pub declared: Option<Vec<String>>,
+    /// Libraries loaded with `dlopen`, which the package recommends or suggests
+    pub dlopened: BTreeMap<Arc<str>, (Relation, DependencySupplier)>,
+    /// What each shared object depends on, by path in the archive
+    pub objects: BTreeMap<String, ObjectDependencies>,
+    /// The oldest version of a library's package that has every symbol version the objects need
+    pub minimum_versions: HashMap<Arc<str>, String>,
 }
 
 pub struct FetchData {
@@ -109,26 +291,89 @@ pub struct FetchData {
     pub dependencies: Dependencies,
     /// The decompressed contents of the .tar.gz archive downloaded from Trunk
     pub archive: Archive,
+    /// Every version of this extension published to the registry
+    pub versions: Vec<ExtensionVersion>,
+    /// The archive's `manifest.json`, if it has one
+    pub manifest: Option<TrunkManifest>,
+    /// The archives of older versions, by version, when their scripts are carried forward
+    pub history: Vec<(String, Archive)>,
+}
+
+impl FetchData {
+    /// The version of the archive itself, which may not be the registry's latest when a local
+    /// file is packaged: the manifest's, or else the `default_version` of the extension's control
+    /// file, or else the registry's latest
+    pub fn archive_version(&self) -> String {
+        if let Some(manifest) = &self.manifest {
+            return manifest.extension_version.clone();
+        }
+
+        let control_file = format!("{}.control", self.extension.name);
+        self.archive
+            .all_entries()
+            .iter()
+            .find(|entry| {
+                entry
+                    .path
+                    .file_name()
+                    .is_some_and(|name| *name == *control_file)
+            })
+            .and_then(|entry| {
+                let contents = String::from_utf8_lossy(&entry.contents);
+                ControlSpec::parse(&self.extension.name, &contents).ok()
+            })
+            .and_then(|spec| spec.default_version)
+            .unwrap_or_else(|| self.extension.latest_version.clone())
+    }
 }
 
 impl Dependencies {
     /// Fetch an extension's dependencies by analyzing its compiled archive
-    pub async fn fetch_from_archive(extension: Extension, client: Client) -> Result<FetchData> {
+    pub async fn fetch_from_archive(
+        extension: Extension,
+        client: Client,
+        pg_major: u16,
+    ) -> Result<FetchData> {
         // Get the archive for this extension
-        let tar_gz = client.fetch_extension_archive(&extension.name).await?;
+        let tar_gz = client
+            .fetch_extension_archive(&extension.name, pg_major)
+            .await?;
+        let versions = client.version_history(&extension.name).await;
 
-        Self::decompress_archive(extension, &tar_gz)
+        Self::decompress_archive(extension, versions, &tar_gz)
     }
 
-    pub fn decompress_archive(extension: Extension, tar_gz_bytes: &[u8]) -> Result<FetchData> {
-        let mut dependencies = Self::new();
-
+    pub fn decompress_archive(
+        extension: Extension,
+        versions: Vec<ExtensionVersion>,
+        tar_gz_bytes: &[u8],
+    ) -> Result<FetchData> {
         let archive = Unarchiver::decompress_in_memory(tar_gz_bytes)?;
 
+        Self::analyze_archive(extension, versions, archive)
+    }
+
+    /// Find the system dependencies of the shared objects of an already decompressed archive
+    pub fn analyze_archive(
+        extension: Extension,
+        versions: Vec<ExtensionVersion>,
+        archive: Archive,
+    ) -> Result<FetchData> {
+        let mut dependencies = Self::new();
+        let mut dlopened = BTreeSet::new();
+
         for entry in archive.shared_objects() {
             let obj = goblin::Object::parse(&entry.contents)?;
             let shared_libraries = match obj {
-                goblin::Object::Elf(elf) => elf.libraries,
+                goblin::Object::Elf(elf) => {
+                    let object = ObjectDependencies::from_elf(&elf, &entry.contents);
+                    dlopened.extend(object.dlopened.iter().map(|library| library.soname.clone()));
+                    dependencies
+                        .objects
+                        .insert(entry.path.display().to_string(), object);
+
+                    elf.libraries
+                }
                 other => {
                     eprintln!(
                         "{} has an unsupported object format: {:?}",
@@ -143,10 +388,32 @@ impl Dependencies {
             }
         }
 
+        // Added once every linked library is known, so that those aren't asked for twice
+        for library in &dlopened {
+            dependencies.add_dlopened(library);
+        }
+        dependencies.require_symbol_versions();
+
+        // A broken manifest only loses what it declares, the archive itself can still be packaged
+        let manifest = TrunkManifest::from_archive(&archive).unwrap_or_else(|err| {
+            eprintln!("{}: ignoring the manifest: {err:#}", extension.name);
+            None
+        });
+        if let Some(manifest) = &manifest {
+            manifest.cross_check(&extension, &archive);
+
+            if let Some(apt) = manifest.apt_dependencies() {
+                dependencies.declare(&extension.name, apt);
+            }
+        }
+
         Ok(FetchData {
             extension,
             dependencies,
             archive,
+            versions,
+            manifest,
+            history: Vec::new(),
         })
     }
 
@@ -154,9 +421,175 @@ impl Dependencies {
         Self {
             shared_libraries: HashSet::with_capacity(8),
             suppliers: HashMap::with_capacity(8),
+            declared: None,
+            dlopened: BTreeMap::new(),
+            objects: BTreeMap::new(),
+            minimum_versions: HashMap::new(),
         }
     }
 
+    /// Whether we know every package this extension depends on
+    pub fn all_known(&self) -> bool {
+        self.declared.is_some() || self.suppliers.values().all(DependencySupplier::is_met)
+    }
+
+    /// The sonames linked against whose packages are unknown, sorted
+    pub fn unknown_sonames(&self) -> Vec<&str> {
+        let mut sonames: Vec<_> = self
+            .suppliers
+            .iter()
+            .filter(|(_, supplier)| supplier.is_met().not())
+            .map(|(soname, _)| &**soname)
+            .collect();
+        sonames.sort_unstable();
+
+        sonames
+    }
+
+    /// Fill in the suppliers the built-in table lacks from the user's mappings
+    pub fn apply_mappings(&mut self, mappings: &SonameMappings) {
+        for (soname, supplier) in &mut self.suppliers {
+            if supplier.is_met().not() {
+                if let Some(mapped) = mappings.supplier(soname) {
+                    *supplier = mapped;
+                }
+            }
+        }
+
+        for (soname, (relation, supplier)) in &mut self.dlopened {
+            if supplier.is_met().not() {
+                if let Some(mapped) = mappings.supplier(soname) {
+                    *relation = Relation::Recommends;
+                    *supplier = mapped;
+                }
+            }
+        }
+
+        // So that the graph of each object names the same suppliers
+        for object in self.objects.values_mut() {
+            for library in &mut object.needed {
+                if library.supplier.is_met().not() {
+                    if let Some(mapped) = mappings.supplier(&library.soname) {
+                        library.supplier = mapped;
+                    }
+                }
+            }
+
+            for library in &mut object.dlopened {
+                if library.supplier.is_met().not() {
+                    if let Some(mapped) = mappings.supplier(&library.soname) {
+                        library.relation = Relation::Recommends;
+                        library.supplier = mapped;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The relations to the packages this extension depends on, sorted, with the package names
+    /// of `distro` if given
+    pub fn packages(&self, distro: Option<&str>) -> Vec<String> {
+        let mut packages: Vec<_> = match &self.declared {
+            Some(declared) => declared.clone(),
+            None => self
+                .suppliers
+                .iter()
+                .map(|(library, supplier)| {
+                    distro
+                        .and_then(|distro| DependencySupplier::distro_override(distro, library))
+                        .map(|supplier| match self.minimum_versions.get(library) {
+                            Some(version) => supplier.at_least(version),
+                            None => supplier,
+                        })
+                        .unwrap_or_else(|| supplier.clone())
+                        .relation()
+                })
+                .collect(),
+        };
+        packages.sort_unstable();
+        packages.dedup();
+
+        packages
+    }
+
+    /// The packages of the libraries loaded with `dlopen` with the given relation, sorted, leaving
+    /// out those the package already depends on
+    pub fn optional_packages(&self, relation: Relation, distro: Option<&str>) -> Vec<String> {
+        let depends = self.packages(distro);
+        let mut packages: Vec<_> = self
+            .dlopened
+            .iter()
+            .filter(|(_, (library_relation, supplier))| {
+                *library_relation == relation && supplier.is_met()
+            })
+            .map(|(library, (_, supplier))| {
+                distro
+                    .and_then(|distro| DependencySupplier::distro_override(distro, library))
+                    .unwrap_or_else(|| supplier.clone())
+                    .relation()
+            })
+            .filter(|package| depends.contains(package).not())
+            .collect();
+        packages.sort_unstable();
+        packages.dedup();
+
+        packages
+    }
+
+    /// Use the packages declared by the manifest, warning about those which disagree with our guesses
+    pub fn declare(&mut self, extension_name: &str, declared: &[String]) {
+        let declared_names: HashSet<&str> = declared
+            .iter()
+            .map(|relation| Self::package_name(relation))
+            .collect();
+
+        for (library, supplier) in &self.suppliers {
+            match supplier {
+                DependencySupplier::MetBy { .. }
+                    if supplier
+                        .packages()
+                        .any(|package| declared_names.contains(package))
+                        .not() =>
+                {
+                    eprintln!(
+                        "{extension_name}: links against {library} (from {}), which the manifest does not declare",
+                        supplier.relation()
+                    );
+                }
+                DependencySupplier::Unknown => {
+                    eprintln!(
+                        "{extension_name}: links against {library}, whose package is unknown; trusting the manifest"
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        let guessed: HashSet<&str> = self
+            .suppliers
+            .values()
+            .chain(self.dlopened.values().map(|(_, supplier)| supplier))
+            .flat_map(DependencySupplier::packages)
+            .collect();
+        for package in &declared_names {
+            if guessed.contains(package).not() {
+                eprintln!(
+                    "{extension_name}: manifest declares {package}, which none of its libraries link against"
+                );
+            }
+        }
+
+        self.declared = Some(declared.to_vec());
+    }
+
+    /// The package name of a relation such as `libfoo1 (>= 1.2)`
+    fn package_name(relation: &str) -> &str {
+        relation
+            .split(|ch: char| ch.is_whitespace() || ch == '(')
+            .next()
+            .unwrap_or(relation)
+    }
+
     pub fn add(&mut self, mut shared_object: &str) {
         if self.shared_libraries.contains(shared_object) {
             // Dependency was already inserted, no more work to do
@@ -166,14 +599,45 @@ impl Dependencies {
             shared_object = "libc.so.6";
         }
 
-        let supplier = DEPENDENCY_SUPPLIERS
-            .get(shared_object)
-            .map(|package| DependencySupplier::MetBy { package })
-            .unwrap_or(DependencySupplier::Unknown);
+        let supplier = DependencySupplier::for_soname(shared_object);
 
         let owned: Arc<str> = Arc::from(shared_object);
 
         self.shared_libraries.insert(owned.clone());
         self.suppliers.insert(owned, supplier);
     }
+
+    /// Constrain libc6 to the newest glibc whose symbol versions, e.g. `GLIBC_2.34`, are needed
+    fn require_symbol_versions(&mut self) {
+        let newest_glibc = self
+            .objects
+            .values()
+            .flat_map(|object| &object.needed)
+            .filter(|library| library.soname == "libc.so.6")
+            .flat_map(|library| &library.versions)
+            .filter_map(|version| version.strip_prefix("GLIBC_"))
+            .filter(|version| version.starts_with(|ch: char| ch.is_ascii_digit()))
+            .max_by(|left, right| utils::compare_versions(left, right));
+        let Some(newest_glibc) = newest_glibc else {
+            return;
+        };
+
+        let library: Arc<str> = Arc::from("libc.so.6");
+        if let Some(supplier) = self.suppliers.get_mut(&library) {
+            *supplier = supplier.clone().at_least(newest_glibc);
+        }
+        self.minimum_versions
+            .insert(library, newest_glibc.to_owned());
+    }
+
+    /// Add a library loaded with `dlopen`, unless some shared object links against it
+    pub fn add_dlopened(&mut self, soname: &str) {
+        if self.shared_libraries.contains(soname) {
+            return;
+        }
+
+        let relation_and_supplier = DependencySupplier::for_dlopened(soname);
+        self.dlopened
+            .insert(Arc::from(soname), relation_and_supplier);
+    }
 }
@@ -0,0 +1,411 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    ops::Not,
+    str::FromStr,
+};
+
+use goblin::elf::Elf;
+use serde::Serialize;
+
+use crate::{
+    dependencies::{DependencySupplier, Relation},
+    dlopen, Result,
+};
+
+/// A library a shared object lists in its `DT_NEEDED` entries
+#[derive(Serialize, Clone, Debug)]
+pub struct NeededLibrary {
+    pub soname: String,
+    /// The symbol versions required from it, e.g. `GLIBC_2.34`
+    pub versions: Vec<String>,
+    pub supplier: DependencySupplier,
+}
+
+/// A library a shared object seemingly loads with `dlopen`
+#[derive(Serialize, Clone, Debug)]
+pub struct DlopenedLibrary {
+    pub soname: String,
+    pub relation: Relation,
+    pub supplier: DependencySupplier,
+}
+
+/// What a single shared object of an archive depends on
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ObjectDependencies {
+    pub rpath: Vec<String>,
+    pub runpath: Vec<String>,
+    pub needed: Vec<NeededLibrary>,
+    pub dlopened: Vec<DlopenedLibrary>,
+}
+
+impl ObjectDependencies {
+    pub fn from_elf(elf: &Elf, bytes: &[u8]) -> Self {
+        let mut versions: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for need in elf.verneed.iter().flat_map(|verneed| verneed.iter()) {
+            let Some(file) = elf.dynstrtab.get_at(need.vn_file) else {
+                continue;
+            };
+            let names = need
+                .iter()
+                .filter_map(|aux| elf.dynstrtab.get_at(aux.vna_name));
+
+            versions.entry(file).or_default().extend(names);
+        }
+
+        let needed = elf
+            .libraries
+            .iter()
+            .map(|soname| NeededLibrary {
+                soname: soname.to_string(),
+                versions: versions
+                    .get(soname)
+                    .into_iter()
+                    .flatten()
+                    .map(|version| version.to_string())
+                    .collect(),
+                supplier: DependencySupplier::resolve(soname),
+            })
+            .collect();
+
+        let dlopened = dlopen::dlopened_libraries(elf, bytes)
+            .into_iter()
+            .map(|soname| {
+                let (relation, supplier) = DependencySupplier::for_dlopened(&soname);
+                DlopenedLibrary {
+                    soname,
+                    relation,
+                    supplier,
+                }
+            })
+            .collect();
+
+        Self {
+            rpath: elf.rpaths.iter().map(|path| path.to_string()).collect(),
+            runpath: elf.runpaths.iter().map(|path| path.to_string()).collect(),
+            needed,
+            dlopened,
+        }
+    }
+}
+
+/// How `show-all` prints the dependency graphs
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum GraphFormat {
+    #[default]
+    Text,
+    Json,
+    Dot,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> std::result::Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "dot" => Ok(Self::Dot),
+            other => Err(format!(
+                "unknown format `{other}`, expected one of text, json or dot"
+            )),
+        }
+    }
+}
+
+/// An extension's shared objects, from archive entry to the sonames they need to their suppliers
+#[derive(Serialize, Debug)]
+pub struct DependencyGraph<'a> {
+    pub extension: &'a str,
+    pub version: &'a str,
+    /// By path in the archive
+    pub objects: &'a BTreeMap<String, ObjectDependencies>,
+}
+
+impl DependencyGraph<'_> {
+    /// The graph as a tree, one shared object after the other
+    pub fn render_tree(&self) -> Result<String> {
+        let mut tree = String::with_capacity(1024);
+
+        for (path, object) in self.objects {
+            writeln!(tree, "  {path}")?;
+            for rpath in &object.rpath {
+                writeln!(tree, "  │   RPATH {rpath}")?;
+            }
+            for runpath in &object.runpath {
+                writeln!(tree, "  │   RUNPATH {runpath}")?;
+            }
+
+            let mut lines = Vec::with_capacity(object.needed.len() + object.dlopened.len());
+            for library in &object.needed {
+                let versions = if library.versions.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", library.versions.join(", "))
+                };
+                lines.push(format!(
+                    "{}{versions} met by {}",
+                    library.soname, library.supplier
+                ));
+            }
+            for library in &object.dlopened {
+                lines.push(format!(
+                    "{} (dlopen) {}: {}",
+                    library.soname, library.relation, library.supplier
+                ));
+            }
+
+            let count = lines.len();
+            for (idx, line) in lines.into_iter().enumerate() {
+                let branch = if idx + 1 == count {
+                    "└──"
+                } else {
+                    "├──"
+                };
+                writeln!(tree, "  {branch} {line}")?;
+            }
+        }
+
+        Ok(tree)
+    }
+
+    /// The graphs of several extensions as a single Graphviz digraph
+    pub fn render_dot(graphs: &[Self]) -> Result<String> {
+        let mut dot = String::with_capacity(4096);
+        writeln!(dot, "digraph dependencies {{")?;
+        writeln!(dot, "  rankdir=LR;")?;
+        writeln!(dot, "  node [shape=box];")?;
+
+        // Sonames and packages are shared between extensions, so they're declared once
+        let mut sonames = BTreeSet::new();
+        let mut packages = BTreeSet::new();
+        let mut edges = BTreeSet::new();
+
+        for graph in graphs {
+            let extension = format!("ext:{}", graph.extension);
+            writeln!(
+                dot,
+                "  {} [label={}, shape=folder];",
+                quote(&extension),
+                quote(&format!("{} {}", graph.extension, graph.version))
+            )?;
+
+            for (path, object) in graph.objects {
+                let object_node = format!("obj:{}:{path}", graph.extension);
+                let file_name = path.rsplit('/').next().unwrap_or(path);
+                writeln!(
+                    dot,
+                    "  {} [label={}];",
+                    quote(&object_node),
+                    quote(file_name)
+                )?;
+                edges.insert((extension.clone(), object_node.clone(), ""));
+
+                let needed = object
+                    .needed
+                    .iter()
+                    .map(|library| (&library.soname, &library.supplier, ""));
+                let dlopened = object
+                    .dlopened
+                    .iter()
+                    .map(|library| (&library.soname, &library.supplier, " [style=dashed]"));
+
+                for (soname, supplier, style) in needed.chain(dlopened) {
+                    let soname_node = format!("so:{soname}");
+                    sonames.insert((soname_node.clone(), soname.clone(), supplier.is_met()));
+                    edges.insert((object_node.clone(), soname_node.clone(), style));
+
+                    for package in supplier.packages() {
+                        let package_node = format!("pkg:{package}");
+                        packages.insert((package_node.clone(), package));
+                        edges.insert((soname_node.clone(), package_node, ""));
+                    }
+                }
+            }
+        }
+
+        for (node, soname, is_met) in &sonames {
+            let color = if is_met.not() { ", color=red" } else { "" };
+            writeln!(
+                dot,
+                "  {} [label={}, shape=ellipse{color}];",
+                quote(node),
+                quote(soname)
+            )?;
+        }
+        for (node, package) in &packages {
+            writeln!(
+                dot,
+                "  {} [label={}, shape=component];",
+                quote(node),
+                quote(package)
+            )?;
+        }
+        for (from, to, style) in &edges {
+            writeln!(dot, "  {} -> {}{style};", quote(from), quote(to))?;
+        }
+
+        writeln!(dot, "}}")?;
+
+        Ok(dot)
+    }
+}
+
+/// A DOT string literal
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::dependencies::{DependencySupplier, Relation};
+
+    use super::{DependencyGraph, DlopenedLibrary, NeededLibrary, ObjectDependencies};
+
+    /// `myext.so` needs libc and an unknown library, and probes for libzstd; `helper.so` only
+    /// needs libc
+    fn objects() -> BTreeMap<String, ObjectDependencies> {
+        let libc = || NeededLibrary {
+            soname: "libc.so.6".into(),
+            versions: vec!["GLIBC_2.34".into()],
+            supplier: DependencySupplier::parse("libc6"),
+        };
+        let myext = ObjectDependencies {
+            rpath: Vec::new(),
+            runpath: vec!["$ORIGIN".into()],
+            needed: vec![
+                libc(),
+                NeededLibrary {
+                    soname: "libfoo.so.1".into(),
+                    versions: Vec::new(),
+                    supplier: DependencySupplier::Unknown,
+                },
+            ],
+            dlopened: vec![DlopenedLibrary {
+                soname: "libzstd.so".into(),
+                relation: Relation::Suggests,
+                supplier: DependencySupplier::parse("libzstd1"),
+            }],
+        };
+        let helper = ObjectDependencies {
+            needed: vec![libc()],
+            ..ObjectDependencies::default()
+        };
+
+        BTreeMap::from([
+            ("lib/helper.so".into(), helper),
+            ("lib/myext.so".into(), myext),
+        ])
+    }
+
+    #[test]
+    fn tree() {
+        let objects = objects();
+        let graph = DependencyGraph {
+            extension: "myext",
+            version: "1.0",
+            objects: &objects,
+        };
+
+        assert_eq!(
+            graph.render_tree().unwrap(),
+            "  lib/helper.so\n\
+             \x20 └── libc.so.6 [GLIBC_2.34] met by \x1b[32mlibc6\x1b[39m\n\
+             \x20 lib/myext.so\n\
+             \x20 │   RUNPATH $ORIGIN\n\
+             \x20 ├── libc.so.6 [GLIBC_2.34] met by \x1b[32mlibc6\x1b[39m\n\
+             \x20 ├── libfoo.so.1 met by \x1b[31m(unknown)\x1b[39m\n\
+             \x20 └── libzstd.so (dlopen) suggested: \x1b[32mlibzstd1\x1b[39m\n"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let objects = objects();
+        let graph = DependencyGraph {
+            extension: "myext",
+            version: "1.0",
+            objects: &objects,
+        };
+
+        let json = serde_json::to_value(&graph).unwrap();
+        assert_eq!(json["extension"], "myext");
+        assert_eq!(json["version"], "1.0");
+        assert_eq!(
+            json["objects"]["lib/myext.so"],
+            serde_json::json!({
+                "rpath": [],
+                "runpath": ["$ORIGIN"],
+                "needed": [
+                    {
+                        "soname": "libc.so.6",
+                        "versions": ["GLIBC_2.34"],
+                        "supplier": {"alternatives": [{"package": "libc6", "version": null}]},
+                    },
+                    {"soname": "libfoo.so.1", "versions": [], "supplier": null},
+                ],
+                "dlopened": [
+                    {
+                        "soname": "libzstd.so",
+                        "relation": "suggests",
+                        "supplier": {"alternatives": [{"package": "libzstd1", "version": null}]},
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn dot() {
+        let objects = objects();
+        let helper_only: BTreeMap<_, _> = objects
+            .iter()
+            .filter(|(path, _)| path.ends_with("helper.so"))
+            .map(|(path, object)| (path.clone(), object.clone()))
+            .collect();
+        let graphs = [
+            DependencyGraph {
+                extension: "myext",
+                version: "1.0",
+                objects: &objects,
+            },
+            DependencyGraph {
+                extension: "other\"ext",
+                version: "2.0",
+                objects: &helper_only,
+            },
+        ];
+
+        // libc is declared once, though both extensions need it
+        assert_eq!(
+            DependencyGraph::render_dot(&graphs).unwrap(),
+            r#"digraph dependencies {
+  rankdir=LR;
+  node [shape=box];
+  "ext:myext" [label="myext 1.0", shape=folder];
+  "obj:myext:lib/helper.so" [label="helper.so"];
+  "obj:myext:lib/myext.so" [label="myext.so"];
+  "ext:other\"ext" [label="other\"ext 2.0", shape=folder];
+  "obj:other\"ext:lib/helper.so" [label="helper.so"];
+  "so:libc.so.6" [label="libc.so.6", shape=ellipse];
+  "so:libfoo.so.1" [label="libfoo.so.1", shape=ellipse, color=red];
+  "so:libzstd.so" [label="libzstd.so", shape=ellipse];
+  "pkg:libc6" [label="libc6", shape=component];
+  "pkg:libzstd1" [label="libzstd1", shape=component];
+  "ext:myext" -> "obj:myext:lib/helper.so";
+  "ext:myext" -> "obj:myext:lib/myext.so";
+  "ext:other\"ext" -> "obj:other\"ext:lib/helper.so";
+  "obj:myext:lib/helper.so" -> "so:libc.so.6";
+  "obj:myext:lib/myext.so" -> "so:libc.so.6";
+  "obj:myext:lib/myext.so" -> "so:libfoo.so.1";
+  "obj:myext:lib/myext.so" -> "so:libzstd.so" [style=dashed];
+  "obj:other\"ext:lib/helper.so" -> "so:libc.so.6";
+  "so:libc.so.6" -> "pkg:libc6";
+  "so:libzstd.so" -> "pkg:libzstd1";
+}
+"#
+        );
+    }
+}
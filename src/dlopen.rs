@@ -0,0 +1,113 @@
+use std::{collections::BTreeSet, ops::Not};
+
+use goblin::elf::{section_header::SHT_PROGBITS, Elf};
+
+/// The functions through which a shared object may load libraries at runtime
+const DLOPEN_FUNCTIONS: [&str; 2] = ["dlopen", "dlmopen"];
+
+/// The libraries a shared object seemingly loads with `dlopen`: if it imports `dlopen`, the
+/// `lib*.so*` strings in its read-only data. Libraries it links against are left out.
+pub fn dlopened_libraries(elf: &Elf, bytes: &[u8]) -> BTreeSet<String> {
+    let imports_dlopen = elf
+        .dynsyms
+        .iter()
+        .filter(|symbol| symbol.is_import())
+        .filter_map(|symbol| elf.dynstrtab.get_at(symbol.st_name))
+        .any(|name| DLOPEN_FUNCTIONS.contains(&name));
+    if imports_dlopen.not() {
+        return BTreeSet::new();
+    }
+
+    let rodata_sections = elf.section_headers.iter().filter(|header| {
+        header.sh_type == SHT_PROGBITS
+            && elf
+                .shdr_strtab
+                .get_at(header.sh_name)
+                .is_some_and(|name| name.starts_with(".rodata"))
+    });
+
+    let mut libraries = BTreeSet::new();
+    for header in rodata_sections {
+        let Some(contents) = header.file_range().and_then(|range| bytes.get(range)) else {
+            continue;
+        };
+
+        let strings = contents
+            .split(|byte| *byte == 0)
+            .filter_map(|string| std::str::from_utf8(string).ok());
+        for string in strings {
+            // Paths such as `/usr/lib/libfoo.so.1` are looked up by their file name
+            let file_name = string.rsplit('/').next().unwrap_or(string);
+            if is_library_name(file_name) {
+                libraries.insert(file_name.to_owned());
+            }
+        }
+    }
+
+    for needed in &elf.libraries {
+        libraries.remove(*needed);
+    }
+
+    libraries
+}
+
+/// Whether `name` looks like `libfoo.so` or `libfoo.so.1.2`
+fn is_library_name(name: &str) -> bool {
+    let Some(stem) = name.strip_prefix("lib") else {
+        return false;
+    };
+    let Some((stem, version)) = stem.split_once(".so") else {
+        return false;
+    };
+
+    let is_stem = stem.is_empty().not()
+        && stem
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || "_+-.".contains(ch));
+    let is_version = version.is_empty()
+        || version.strip_prefix('.').is_some_and(|version| {
+            version
+                .split('.')
+                .all(|part| part.is_empty().not() && part.chars().all(|ch| ch.is_ascii_digit()))
+        });
+
+    is_stem && is_version
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Not;
+
+    use super::is_library_name;
+
+    #[test]
+    fn library_names() {
+        for name in [
+            "libfoo.so",
+            "libfoo.so.1",
+            "libfoo.so.1.2.3",
+            "libstdc++.so.6",
+            "libgdal-3.so.34",
+            "libpython3.10.so.1.0",
+        ] {
+            assert!(is_library_name(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn not_library_names() {
+        for name in [
+            "foo.so",
+            "lib.so",
+            "libfoo",
+            "libfoo.so.",
+            "libfoo.so.1..2",
+            "libfoo.so.1a",
+            "libfoo.sox",
+            "libfoo bar.so",
+            "libfoo.so %s",
+        ] {
+            assert!(is_library_name(name).not(), "{name}");
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    ops::Not,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    unarchiver::{Archive, EntryKind},
+    Result,
+};
+
+/// A PostgreSQL extension's `.control` file.
+///
+/// Docs.: <https://www.postgresql.org/docs/current/extend-extensions.html#EXTEND-EXTENSIONS-FILES>
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ControlSpec {
+    /// The extension's name, taken from the file name
+    pub name: String,
+    pub comment: Option<String>,
+    pub default_version: Option<String>,
+    pub module_pathname: Option<String>,
+    pub requires: Vec<String>,
+    pub superuser: bool,
+    pub trusted: bool,
+    pub relocatable: bool,
+    pub schema: Option<String>,
+    /// Where the SQL scripts are, relative to `SHAREDIR` unless absolute
+    pub directory: Option<String>,
+}
+
+impl ControlSpec {
+    /// Parse the control file of the extension `name`
+    pub fn parse(name: &str, contents: &str) -> Result<Self> {
+        let mut spec = Self {
+            name: name.to_owned(),
+            comment: None,
+            default_version: None,
+            module_pathname: None,
+            requires: Vec::new(),
+            superuser: true,
+            trusted: false,
+            relocatable: false,
+            schema: None,
+            directory: None,
+        };
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let Some((key, value)) = Self::parse_line(line)
+                .with_context(|| format!("{name}.control, line {}", line_number + 1))?
+            else {
+                continue;
+            };
+
+            match key.as_str() {
+                "comment" => spec.comment = Some(value),
+                "default_version" => spec.default_version = Some(value),
+                "module_pathname" => spec.module_pathname = Some(value),
+                "requires" => {
+                    spec.requires = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|requirement| requirement.is_empty().not())
+                        .map(str::to_owned)
+                        .collect()
+                }
+                "superuser" => spec.superuser = Self::parse_bool(&key, &value)?,
+                "trusted" => spec.trusted = Self::parse_bool(&key, &value)?,
+                "relocatable" => spec.relocatable = Self::parse_bool(&key, &value)?,
+                "schema" => spec.schema = Some(value),
+                "directory" => spec.directory = Some(value),
+                // e.g. `encoding`, or settings of newer PostgreSQL versions
+                _ => {}
+            }
+        }
+
+        Ok(spec)
+    }
+
+    /// Split a line into its parameter and value, following the syntax of `postgresql.conf`:
+    /// `name = value` or `name value`, where the value may be a single-quoted string, and `#`
+    /// starts a comment
+    fn parse_line(line: &str) -> Result<Option<(String, String)>> {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let key_len = line
+            .find(|ch: char| (ch.is_ascii_alphanumeric() || ch == '_' || ch == '.').not())
+            .unwrap_or(line.len());
+        if key_len == 0 {
+            bail!("Expected a parameter name in `{line}`");
+        }
+        let key = line[..key_len].to_ascii_lowercase();
+
+        let rest = line[key_len..].trim_start();
+        let rest = rest.strip_prefix('=').unwrap_or(rest).trim_start();
+
+        let (value, rest) = match rest.strip_prefix('\'') {
+            Some(quoted) => Self::parse_quoted(quoted)?,
+            None => {
+                let len = rest
+                    .find(|ch: char| ch.is_whitespace() || ch == '#')
+                    .unwrap_or(rest.len());
+                (rest[..len].to_owned(), &rest[len..])
+            }
+        };
+
+        let rest = rest.trim_start();
+        if rest.is_empty().not() && rest.starts_with('#').not() {
+            bail!("Unexpected `{rest}` after the value of {key}");
+        }
+
+        Ok(Some((key, value)))
+    }
+
+    /// Read a single-quoted string up to its closing quote, where `''` and `\'` stand for a quote
+    fn parse_quoted(quoted: &str) -> Result<(String, &str)> {
+        let mut value = String::with_capacity(quoted.len());
+        let mut chars = quoted.char_indices().peekable();
+
+        while let Some((idx, ch)) = chars.next() {
+            match ch {
+                '\'' if chars.peek().is_some_and(|(_, next)| *next == '\'') => {
+                    chars.next();
+                    value.push('\'');
+                }
+                '\'' => return Ok((value, &quoted[idx + 1..])),
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, 'r')) => value.push('\r'),
+                    Some((_, escaped)) => value.push(escaped),
+                    None => break,
+                },
+                _ => value.push(ch),
+            }
+        }
+
+        bail!("Unterminated quoted string")
+    }
+
+    /// Booleans as PostgreSQL reads them: `on`, `true`, `yes`, `1` and their opposites
+    fn parse_bool(key: &str, value: &str) -> Result<bool> {
+        match value.to_ascii_lowercase().as_str() {
+            "on" | "true" | "t" | "yes" | "y" | "1" => Ok(true),
+            "off" | "false" | "f" | "no" | "n" | "0" => Ok(false),
+            _ => bail!("{key} requires a Boolean value, got `{value}`"),
+        }
+    }
+
+    /// The primary control files of the extensions in `archive`, by extension name, along with
+    /// why those which can't be parsed were left out. Secondary control files such as
+    /// `foo--1.0.control` are left out as well.
+    pub fn from_archive(archive: &Archive) -> (BTreeMap<String, Self>, Vec<anyhow::Error>) {
+        let mut specs = BTreeMap::new();
+        let mut errors = Vec::new();
+
+        for entry in archive.all_entries() {
+            let is_control_file = entry.kind == EntryKind::Regular
+                && entry.path.extension().is_some_and(|ext| ext == "control");
+            let Some(name) = entry.path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if is_control_file.not() || name.contains("--") {
+                continue;
+            }
+
+            let contents = String::from_utf8_lossy(&entry.contents);
+            match Self::parse(name, &contents) {
+                Ok(spec) => {
+                    specs.insert(name.to_owned(), spec);
+                }
+                Err(err) => errors.push(err.context(format!("Ignoring {}", entry.path.display()))),
+            }
+        }
+
+        (specs, errors)
+    }
+
+    /// The name of the library in `module_pathname`, e.g. `foo` for `$libdir/foo`
+    pub fn module_library(&self) -> Option<&str> {
+        let pathname = self.module_pathname.as_deref()?;
+        let library = pathname.strip_prefix("$libdir/").unwrap_or(pathname);
+
+        Some(library.strip_suffix(".so").unwrap_or(library))
+    }
+
+    /// Where the SQL scripts are installed, given the installation's `SHAREDIR`
+    pub fn script_directory(&self, sharedir: &str) -> PathBuf {
+        let directory = self.directory.as_deref().unwrap_or("extension");
+
+        // A relative directory is relative to SHAREDIR, and `join` keeps absolute ones as they are
+        Path::new(sharedir).join(directory)
+    }
+
+    /// A few lines about the extension, fit for a package's extended description
+    pub fn describe(&self) -> Result<String> {
+        let mut description = String::with_capacity(256);
+
+        write!(description, "PostgreSQL extension {}", self.name)?;
+        if let Some(version) = &self.default_version {
+            write!(description, ", version {version}")?;
+        }
+        writeln!(description, ".")?;
+
+        if self.requires.is_empty().not() {
+            writeln!(description, "Requires: {}.", self.requires.join(", "))?;
+        }
+        if self.superuser.not() || self.trusted {
+            writeln!(description, "Can be installed by non-superusers.")?;
+        }
+        if self.relocatable {
+            writeln!(description, "Can be moved to another schema.")?;
+        }
+        if let Some(schema) = &self.schema {
+            writeln!(description, "Installed into schema {schema}.")?;
+        }
+
+        Ok(description)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Not;
+
+    use crate::unarchiver::{Archive, Entry, EntryKind};
+
+    use super::ControlSpec;
+
+    #[test]
+    fn parse() {
+        let spec = ControlSpec::parse(
+            "myext",
+            "# myext extension\n\
+             comment = 'It''s my extension, with a \\'quote\\' # not a comment'\n\
+             default_version = '1.2'   # the newest\n\
+             MODULE_PATHNAME = '$libdir/myext'\n\
+             \n\
+             requires = 'plpgsql, hstore,,cube '\n\
+             superuser = false\n\
+             trusted true\n\
+             relocatable = on\n\
+             schema=myschema\n\
+             encoding = UTF8\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            spec,
+            ControlSpec {
+                name: "myext".into(),
+                comment: Some("It's my extension, with a 'quote' # not a comment".into()),
+                default_version: Some("1.2".into()),
+                module_pathname: Some("$libdir/myext".into()),
+                requires: vec!["plpgsql".into(), "hstore".into(), "cube".into()],
+                superuser: false,
+                trusted: true,
+                relocatable: true,
+                schema: Some("myschema".into()),
+                directory: None,
+            }
+        );
+        assert_eq!(spec.module_library(), Some("myext"));
+    }
+
+    #[test]
+    fn defaults() {
+        let spec = ControlSpec::parse("myext", "").unwrap();
+
+        assert!(spec.superuser);
+        assert!(spec.trusted.not() && spec.relocatable.not());
+        assert_eq!(spec.module_library(), None);
+        assert_eq!(
+            spec.script_directory("/usr/share/postgresql/16"),
+            std::path::Path::new("/usr/share/postgresql/16/extension")
+        );
+    }
+
+    #[test]
+    fn bad_lines() {
+        for (contents, error) in [
+            (
+                "= '1.0'",
+                "myext.control, line 1: Expected a parameter name in `= '1.0'`",
+            ),
+            (
+                "\ndefault_version = 1.0 beta",
+                "myext.control, line 2: Unexpected `beta` after the value of default_version",
+            ),
+            (
+                "comment = 'unterminated",
+                "myext.control, line 1: Unterminated quoted string",
+            ),
+            (
+                "trusted = maybe",
+                "trusted requires a Boolean value, got `maybe`",
+            ),
+        ] {
+            let err = ControlSpec::parse("myext", contents).unwrap_err();
+
+            assert_eq!(format!("{err:#}"), error);
+        }
+    }
+
+    #[test]
+    fn from_archive() {
+        let mut archive = Archive::default();
+        for (path, contents) in [
+            ("extension/myext.control", "default_version = '1.0'"),
+            ("extension/myext--1.0.control", "this is not parsed"),
+            ("extension/broken.control", "comment = 'unterminated"),
+        ] {
+            archive.push(Entry {
+                path: path.into(),
+                contents: contents.as_bytes().to_vec(),
+                mtime: 0,
+                kind: EntryKind::Regular,
+                mode: 0o644,
+                link_target: None,
+            });
+        }
+
+        let (specs, errors) = ControlSpec::from_archive(&archive);
+        assert_eq!(specs.keys().collect::<Vec<_>>(), ["myext"]);
+        assert_eq!(specs["myext"].default_version.as_deref(), Some("1.0"));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            format!("{:#}", errors[0]),
+            "Ignoring extension/broken.control: broken.control, line 1: Unterminated quoted string"
+        );
+    }
+}
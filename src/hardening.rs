@@ -0,0 +1,453 @@
+use std::{
+    fmt::Display,
+    ops::Not,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use goblin::elf::{
+    dynamic::{DF_1_NOW, DF_BIND_NOW, DF_TEXTREL, DT_BIND_NOW, DT_FLAGS, DT_FLAGS_1, DT_TEXTREL},
+    program_header::{PF_X, PT_GNU_RELRO, PT_GNU_STACK},
+    Elf,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{deb_reader::DebFile, unarchiver::Archive, utils, Result};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Relro {
+    None,
+    /// `PT_GNU_RELRO` without immediate binding: the GOT stays writable
+    Partial,
+    Full,
+}
+
+/// The exploit mitigations a shared object was built with
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Hardening {
+    pub relro: Relro,
+    /// `PT_GNU_STACK` is present and not executable
+    pub nx_stack: bool,
+    /// Relocations in read-only segments, which need them made writable at load time
+    pub textrel: bool,
+    /// The `__*_chk` functions imported because of `_FORTIFY_SOURCE`
+    pub fortified: Vec<String>,
+}
+
+/// A hardening property a policy can require
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Property {
+    FullRelro,
+    NxStack,
+    NoTextrel,
+    Fortify,
+}
+
+impl Display for Property {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Property::FullRelro => "full RELRO",
+            Property::NxStack => "NX stack",
+            Property::NoTextrel => "no TEXTREL",
+            Property::Fortify => "FORTIFY",
+        };
+
+        f.write_str(name)
+    }
+}
+
+impl Hardening {
+    pub fn analyze(bytes: &[u8]) -> Result<Self> {
+        let elf = Elf::parse(bytes)?;
+
+        let has_relro = elf
+            .program_headers
+            .iter()
+            .any(|header| header.p_type == PT_GNU_RELRO);
+        let nx_stack = elf
+            .program_headers
+            .iter()
+            .find(|header| header.p_type == PT_GNU_STACK)
+            .is_some_and(|header| header.p_flags & PF_X == 0);
+
+        let dyns = elf
+            .dynamic
+            .as_ref()
+            .map(|dynamic| dynamic.dyns.as_slice())
+            .unwrap_or_default();
+        let has_flag = |tag: u64, flag: u64| {
+            dyns.iter()
+                .any(|entry| entry.d_tag == tag && entry.d_val & flag != 0)
+        };
+        let has_tag = |tag: u64| dyns.iter().any(|entry| entry.d_tag == tag);
+
+        let bind_now = has_tag(DT_BIND_NOW)
+            || has_flag(DT_FLAGS, DF_BIND_NOW)
+            || has_flag(DT_FLAGS_1, DF_1_NOW);
+        let relro = match (has_relro, bind_now) {
+            (true, true) => Relro::Full,
+            (true, false) => Relro::Partial,
+            (false, _) => Relro::None,
+        };
+        let textrel = has_tag(DT_TEXTREL) || has_flag(DT_FLAGS, DF_TEXTREL);
+
+        let mut fortified: Vec<_> = elf
+            .dynsyms
+            .iter()
+            .filter(|symbol| symbol.is_import())
+            .filter_map(|symbol| elf.dynstrtab.get_at(symbol.st_name))
+            .filter(|name| name.starts_with("__") && name.ends_with("_chk"))
+            .map(str::to_owned)
+            .collect();
+        fortified.sort_unstable();
+        fortified.dedup();
+
+        Ok(Self {
+            relro,
+            nx_stack,
+            textrel,
+            fortified,
+        })
+    }
+
+    pub fn has(&self, property: Property) -> bool {
+        match property {
+            Property::FullRelro => self.relro == Relro::Full,
+            Property::NxStack => self.nx_stack,
+            Property::NoTextrel => self.textrel.not(),
+            Property::Fortify => self.fortified.is_empty().not(),
+        }
+    }
+}
+
+impl Display for Hardening {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let relro = match self.relro {
+            Relro::None => "no RELRO",
+            Relro::Partial => "partial RELRO",
+            Relro::Full => "full RELRO",
+        };
+        let stack = if self.nx_stack {
+            "NX stack"
+        } else {
+            "executable stack"
+        };
+        let textrel = if self.textrel {
+            "TEXTREL"
+        } else {
+            "no TEXTREL"
+        };
+
+        write!(f, "{relro}, {stack}, {textrel}, ")?;
+        match self.fortified.len() {
+            0 => write!(f, "not fortified"),
+            count => write!(f, "fortified ({count} __*_chk)"),
+        }
+    }
+}
+
+/// Which hardening properties libraries must have, read from a TOML file such as:
+///
+/// ```toml
+/// require = ["nx-stack", "no-textrel"]
+/// forbid_regressions = ["full-relro", "fortify"]
+/// ```
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HardeningPolicy {
+    /// Properties every shared object must have
+    #[serde(default)]
+    pub require: Vec<Property>,
+    /// Properties a shared object may not lose compared to the previous version's package
+    #[serde(default)]
+    pub forbid_regressions: Vec<Property>,
+}
+
+impl HardeningPolicy {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = utils::read_to_vec(path)?;
+        let contents = String::from_utf8(contents)
+            .with_context(|| format!("{} is not valid UTF-8", path.display()))?;
+
+        toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse hardening policy {}", path.display()))
+    }
+
+    /// Check the shared objects of `archive` against this policy. Regressions are looked for
+    /// in the newest package of an older version of `package` found in `export_dir`.
+    pub fn check(
+        &self,
+        package: &str,
+        version: &str,
+        archive: &Archive,
+        export_dir: &Path,
+    ) -> Result {
+        let previous = if self.forbid_regressions.is_empty() {
+            None
+        } else {
+            Self::previous_package(package, version, export_dir)?
+        };
+
+        let mut violations = Vec::new();
+
+        for entry in archive.shared_objects() {
+            let Some(file_name) = entry.path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let hardening = Hardening::analyze(&entry.contents)
+                .with_context(|| format!("Failed to analyze {file_name}"))?;
+
+            for property in &self.require {
+                if hardening.has(*property).not() {
+                    violations.push(format!("{file_name} is not built with {property}"));
+                }
+            }
+
+            let Some((previous_version, previous)) = &previous else {
+                continue;
+            };
+            let previous_hardening = previous
+                .hardening
+                .iter()
+                .find(|(path, _)| path.rsplit('/').next() == Some(file_name));
+            let Some((_, previous_hardening)) = previous_hardening else {
+                continue;
+            };
+
+            for property in &self.forbid_regressions {
+                if previous_hardening.has(*property) && hardening.has(*property).not() {
+                    violations.push(format!(
+                        "{file_name} lost {property}, which version {previous_version} had"
+                    ));
+                }
+            }
+        }
+
+        if violations.is_empty().not() {
+            bail!(
+                "{package} violates the hardening policy:\n  {}",
+                violations.join("\n  ")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The package of the newest version of `package` older than `version` in `export_dir`
+    fn previous_package(
+        package: &str,
+        version: &str,
+        export_dir: &Path,
+    ) -> Result<Option<(String, DebFile)>> {
+        let prefix = format!("{package}_");
+        let mut newest: Option<(String, PathBuf)> = None;
+
+        for dir_entry in fs_err::read_dir(export_dir)? {
+            let path = dir_entry?.path();
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let Some(candidate) = file_name
+                .strip_prefix(&prefix)
+                .filter(|_| file_name.ends_with(".deb"))
+                .and_then(|rest| rest.split('_').next())
+            else {
+                continue;
+            };
+
+            let is_older = utils::compare_versions(candidate, version).is_lt();
+            let is_newest = newest
+                .as_ref()
+                .is_none_or(|(newest, _)| utils::compare_versions(candidate, newest).is_gt());
+            if is_older && is_newest {
+                newest = Some((candidate.to_owned(), path));
+            }
+        }
+
+        newest
+            .map(|(version, path)| DebFile::read(&path).map(|deb| (version, deb)))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, ops::Not, path::Path};
+
+    use crate::unarchiver::{Archive, Entry, EntryKind};
+
+    use super::{Hardening, HardeningPolicy, Property, Relro};
+
+    /// Built by `tests/fixtures/build.sh` with full RELRO and `_FORTIFY_SOURCE`
+    const HARDENED: &[u8] = include_bytes!("../tests/fixtures/libhardened.so");
+    /// Built by `tests/fixtures/build.sh` with text relocations, no RELRO and an executable stack
+    const UNHARDENED: &[u8] = include_bytes!("../tests/fixtures/libunhardened.so");
+
+    fn archive(contents: &[u8]) -> Archive {
+        let mut archive = Archive::default();
+        archive.push(Entry {
+            path: "lib/myext.so".into(),
+            contents: contents.to_vec(),
+            mtime: 0,
+            kind: EntryKind::Regular,
+            mode: 0o755,
+            link_target: None,
+        });
+        archive
+    }
+
+    /// A minimal package of `version` shipping `contents` as `myext.so`
+    fn write_deb(export_dir: &Path, version: &str, contents: &[u8]) {
+        let tar = |files: &[(&str, &[u8])]| {
+            let mut builder = tar::Builder::new(Vec::new());
+            for (path, contents) in files {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(contents.len() as u64);
+                header.set_mode(0o644);
+                builder.append_data(&mut header, path, *contents).unwrap();
+            }
+            builder.into_inner().unwrap()
+        };
+        let control = format!(
+            "Package: postgresql-16-myext\nVersion: {version}\nArchitecture: amd64\n\
+             Description: My extension\n"
+        );
+        let control_tar = tar(&[("./control", control.as_bytes())]);
+        let data_tar = tar(&[("./usr/lib/postgresql/16/lib/myext.so", contents)]);
+
+        let path = export_dir.join(format!("postgresql-16-myext_{version}_amd64.deb"));
+        let mut builder = ar::Builder::new(File::create(path).unwrap());
+        for (name, data) in [
+            ("debian-binary", b"2.0\n".as_slice()),
+            ("control.tar", &control_tar),
+            ("data.tar", &data_tar),
+        ] {
+            let header = ar::Header::new(name.into(), data.len() as u64);
+            builder.append(&header, data).unwrap();
+        }
+    }
+
+    #[test]
+    fn hardened() {
+        let hardening = Hardening::analyze(HARDENED).unwrap();
+
+        assert_eq!(hardening.relro, Relro::Full);
+        assert!(hardening.nx_stack);
+        assert!(hardening.textrel.not());
+        assert_eq!(hardening.fortified, ["__sprintf_chk"]);
+        for property in [
+            Property::FullRelro,
+            Property::NxStack,
+            Property::NoTextrel,
+            Property::Fortify,
+        ] {
+            assert!(hardening.has(property), "{property} is missing");
+        }
+        assert_eq!(
+            hardening.to_string(),
+            "full RELRO, NX stack, no TEXTREL, fortified (1 __*_chk)"
+        );
+    }
+
+    #[test]
+    fn unhardened() {
+        let hardening = Hardening::analyze(UNHARDENED).unwrap();
+
+        assert_eq!(hardening.relro, Relro::None);
+        assert!(hardening.nx_stack.not());
+        assert!(hardening.textrel);
+        assert!(hardening.fortified.is_empty());
+        for property in [
+            Property::FullRelro,
+            Property::NxStack,
+            Property::NoTextrel,
+            Property::Fortify,
+        ] {
+            assert!(hardening.has(property).not(), "{property} is present");
+        }
+        assert_eq!(
+            hardening.to_string(),
+            "no RELRO, executable stack, TEXTREL, not fortified"
+        );
+    }
+
+    #[test]
+    fn require() {
+        let policy: HardeningPolicy =
+            toml::from_str(r#"require = ["nx-stack", "no-textrel"]"#).unwrap();
+        let export_dir = tempfile::tempdir().unwrap();
+
+        policy
+            .check(
+                "postgresql-16-myext",
+                "1.0",
+                &archive(HARDENED),
+                export_dir.path(),
+            )
+            .unwrap();
+        let err = policy
+            .check(
+                "postgresql-16-myext",
+                "1.0",
+                &archive(UNHARDENED),
+                export_dir.path(),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "postgresql-16-myext violates the hardening policy:\n  \
+             myext.so is not built with NX stack\n  \
+             myext.so is not built with no TEXTREL"
+        );
+    }
+
+    #[test]
+    fn forbid_regressions() {
+        let policy: HardeningPolicy =
+            toml::from_str(r#"forbid_regressions = ["full-relro", "fortify"]"#).unwrap();
+        let export_dir = tempfile::tempdir().unwrap();
+        write_deb(export_dir.path(), "0.9", UNHARDENED);
+        write_deb(export_dir.path(), "1.0", HARDENED);
+        // Newer than the version being checked, so never compared against
+        write_deb(export_dir.path(), "1.2", HARDENED);
+
+        let err = policy
+            .check(
+                "postgresql-16-myext",
+                "1.1",
+                &archive(UNHARDENED),
+                export_dir.path(),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "postgresql-16-myext violates the hardening policy:\n  \
+             myext.so lost full RELRO, which version 1.0 had\n  \
+             myext.so lost FORTIFY, which version 1.0 had"
+        );
+
+        // 0.9 had nothing to lose
+        policy
+            .check(
+                "postgresql-16-myext",
+                "1.0",
+                &archive(UNHARDENED),
+                export_dir.path(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let err = toml::from_str::<HardeningPolicy>(r#"required = ["nx-stack"]"#).unwrap_err();
+        assert!(
+            err.to_string().contains("unknown field `required`"),
+            "{err}"
+        );
+
+        let err = toml::from_str::<HardeningPolicy>(r#"require = ["pie"]"#).unwrap_err();
+        assert!(err.to_string().contains("unknown variant `pie`"), "{err}");
+    }
+}
@@ -0,0 +1,403 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use globset::{GlobBuilder, GlobMatcher};
+use serde::Deserialize;
+
+use crate::{
+    split::SubPackage,
+    unarchiver::{Entry, EntryKind},
+    utils, Result,
+};
+
+/// The directories extension files are installed into, named as `pg_config` reports them
+#[derive(Clone, Debug)]
+pub struct Layout {
+    pub bindir: String,
+    pub pkglibdir: String,
+    pub sharedir: String,
+    pub docdir: String,
+    pub includedir_server: String,
+}
+
+impl Layout {
+    /// The layout of Debian's `postgresql-<major>` packages
+    pub fn debian(pg_major: u16) -> Self {
+        Self {
+            bindir: format!("/usr/lib/postgresql/{pg_major}/bin"),
+            pkglibdir: format!("/usr/lib/postgresql/{pg_major}/lib"),
+            sharedir: format!("/usr/share/postgresql/{pg_major}"),
+            docdir: format!("/usr/share/doc/postgresql-doc-{pg_major}"),
+            includedir_server: format!("/usr/include/postgresql/{pg_major}/server"),
+        }
+    }
+
+    /// Read a layout from a file holding the output of `pg_config`, e.g. `PKGLIBDIR = /usr/lib/postgresql/16/lib`
+    pub fn from_pg_config(path: &Path) -> Result<Self> {
+        let contents = utils::read_to_vec(path)?;
+        let contents = String::from_utf8(contents)
+            .with_context(|| format!("{} is not valid UTF-8", path.display()))?;
+
+        let settings: HashMap<&str, &str> = contents
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .collect();
+
+        let setting = |key: &str| {
+            settings
+                .get(key)
+                .map(|value| value.to_string())
+                .with_context(|| format!("{} does not set {key}", path.display()))
+        };
+
+        Ok(Self {
+            bindir: setting("BINDIR")?,
+            pkglibdir: setting("PKGLIBDIR")?,
+            sharedir: setting("SHAREDIR")?,
+            docdir: setting("DOCDIR")?,
+            includedir_server: setting("INCLUDEDIR-SERVER")?,
+        })
+    }
+
+    pub fn bitcodedir(&self) -> String {
+        format!("{}/bitcode", self.pkglibdir)
+    }
+}
+
+/// How a rule is written down in a rules file
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSpec {
+    /// Glob matched against the entry's path within the Trunk archive
+    pub glob: String,
+    /// Directory template matching entries are installed into, e.g. `{sharedir}/extension`.
+    /// Entries matched by a rule without a destination are left out of the package.
+    pub dest: Option<String>,
+    /// Permissions of the installed files. When unset, they're derived from the entry itself.
+    pub mode: Option<u32>,
+    /// Keep the entry's path below the glob's literal prefix instead of only its file name
+    #[serde(default)]
+    pub keep_subdirs: bool,
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// The binary package matching entries go into, `main` by default
+    #[serde(default)]
+    pub package: SubPackage,
+}
+
+/// An install rule: entries matching `glob` are installed into `dest`
+#[derive(Clone, Debug)]
+pub struct Rule {
+    spec: RuleSpec,
+    matcher: GlobMatcher,
+    /// The leading components of the glob which hold no wildcards, e.g. `bitcode` in `bitcode/**`
+    literal_prefix: PathBuf,
+}
+
+impl Rule {
+    pub fn new(spec: RuleSpec) -> Result<Self> {
+        let matcher = GlobBuilder::new(&spec.glob)
+            .literal_separator(true)
+            .case_insensitive(spec.case_insensitive)
+            .build()
+            .with_context(|| format!("Invalid glob `{}`", spec.glob))?
+            .compile_matcher();
+
+        let literal_prefix = spec
+            .glob
+            .split('/')
+            .take_while(|component| !component.contains(['*', '?', '[', '{']))
+            .collect();
+
+        Ok(Self {
+            spec,
+            matcher,
+            literal_prefix,
+        })
+    }
+
+    fn install(glob: &str, dest: &str) -> Self {
+        Self::new(RuleSpec {
+            glob: glob.into(),
+            dest: Some(dest.into()),
+            mode: None,
+            keep_subdirs: false,
+            case_insensitive: false,
+            package: SubPackage::Main,
+        })
+        .expect("built-in rules are valid")
+    }
+
+    fn with_mode(mut self, mode: u32) -> Self {
+        self.spec.mode = Some(mode);
+        self
+    }
+
+    fn keeping_subdirs(mut self) -> Self {
+        self.spec.keep_subdirs = true;
+        self
+    }
+
+    fn in_package(mut self, package: SubPackage) -> Self {
+        self.spec.package = package;
+        self
+    }
+
+    fn case_insensitive(glob: &str, dest: &str) -> Self {
+        Self::new(RuleSpec {
+            glob: glob.into(),
+            dest: Some(dest.into()),
+            mode: None,
+            keep_subdirs: false,
+            case_insensitive: true,
+            package: SubPackage::Main,
+        })
+        .expect("built-in rules are valid")
+    }
+
+    fn skip_case_insensitive(glob: &str) -> Self {
+        Self::new(RuleSpec {
+            glob: glob.into(),
+            dest: None,
+            mode: None,
+            keep_subdirs: false,
+            case_insensitive: true,
+            package: SubPackage::Main,
+        })
+        .expect("built-in rules are valid")
+    }
+
+    fn skip(glob: &str) -> Self {
+        Self::new(RuleSpec {
+            glob: glob.into(),
+            dest: None,
+            mode: None,
+            keep_subdirs: false,
+            case_insensitive: false,
+            package: SubPackage::Main,
+        })
+        .expect("built-in rules are valid")
+    }
+
+    pub fn is_match(&self, path: &Path) -> bool {
+        self.matcher.is_match(path)
+    }
+}
+
+/// Values substituted into the destination templates
+pub struct TemplateContext<'a> {
+    pub layout: &'a Layout,
+    /// The name of the extension being packaged
+    pub extension: &'a str,
+    /// The name of the main Debian package being built
+    pub package: &'a str,
+}
+
+impl TemplateContext<'_> {
+    fn variable(&self, name: &str, sub_package: SubPackage) -> Option<String> {
+        let value = match name {
+            "bindir" => self.layout.bindir.clone(),
+            "pkglibdir" => self.layout.pkglibdir.clone(),
+            "sharedir" => self.layout.sharedir.clone(),
+            "docdir" => self.layout.docdir.clone(),
+            "includedir_server" => self.layout.includedir_server.clone(),
+            "bitcodedir" => self.layout.bitcodedir(),
+            // Each package has a documentation directory of its own
+            "pkgdocdir" => format!("/usr/share/doc/{}", sub_package.package_name(self.package)),
+            "extension" => self.extension.to_owned(),
+            "package" => self.package.to_owned(),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    /// Substitute every `{variable}` in `template`, the destination of a file of `sub_package`
+    pub fn expand(&self, template: &str, sub_package: SubPackage) -> Result<String> {
+        let mut expanded = String::with_capacity(template.len() * 2);
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                bail!("Unclosed `{{` in destination `{template}`");
+            };
+            let name = &rest[start + 1..start + len];
+            let value = self
+                .variable(name, sub_package)
+                .with_context(|| format!("Unknown variable `{{{name}}}` in `{template}`"))?;
+
+            expanded.push_str(&rest[..start]);
+            expanded.push_str(&value);
+            rest = &rest[start + len + 1..];
+        }
+        expanded.push_str(rest);
+
+        Ok(expanded)
+    }
+}
+
+/// Where an entry of the Trunk archive ends up in the package
+pub struct Placement {
+    /// Absolute install path
+    pub target: PathBuf,
+    pub mode: u32,
+    /// The binary package the entry goes into
+    pub package: SubPackage,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    /// Rules tried for every extension, before the built-in ones
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleSpec>,
+    /// Rules tried only for the given extension, before any other
+    #[serde(default, rename = "extension")]
+    extensions: HashMap<String, ExtensionRules>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ExtensionRules {
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleSpec>,
+}
+
+/// The ordered install rules. The first rule matching an entry decides its placement.
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    per_extension: HashMap<String, Vec<Rule>>,
+}
+
+impl RuleSet {
+    /// Rules matching the layout of Debian's own PostgreSQL extension packages
+    pub fn debian() -> Self {
+        let rules = vec![
+            Rule::skip("manifest.json"),
+            Rule::install("**/*.control", "{sharedir}/extension"),
+            Rule::install("**/*.sql", "{sharedir}/extension"),
+            Rule::install("bitcode/**", "{bitcodedir}")
+                .keeping_subdirs()
+                .in_package(SubPackage::Jit),
+            Rule::install("*.index.bc", "{bitcodedir}").in_package(SubPackage::Jit),
+            Rule::install("**/*.bc", "{bitcodedir}/{extension}").in_package(SubPackage::Jit),
+            Rule::install("**/*.so", "{pkglibdir}").with_mode(0o644),
+            Rule::install("**/*.so.[0-9]*", "{pkglibdir}").with_mode(0o644),
+            Rule::install("bin/*", "{bindir}").with_mode(0o755),
+            Rule::install("include/server/**", "{includedir_server}")
+                .keeping_subdirs()
+                .in_package(SubPackage::Dev),
+            // License texts are shipped within the generated `copyright` file
+            Rule::skip_case_insensitive("**/{LICENSE,LICENCE,COPYING,NOTICE}*"),
+            Rule::case_insensitive("**/{README,CHANGELOG}*", "{pkgdocdir}")
+                .in_package(SubPackage::Doc),
+            Rule::install("doc/**", "{pkgdocdir}").in_package(SubPackage::Doc),
+        ];
+
+        Self {
+            rules,
+            per_extension: HashMap::new(),
+        }
+    }
+
+    /// Load rules from a TOML file, which are tried before the built-in ones
+    pub fn with_overrides(mut self, path: &Path) -> Result<Self> {
+        let contents = utils::read_to_vec(path)?;
+        let contents = String::from_utf8(contents)
+            .with_context(|| format!("{} is not valid UTF-8", path.display()))?;
+        let file: RulesFile = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse rules file {}", path.display()))?;
+
+        let mut rules = file
+            .rules
+            .into_iter()
+            .map(Rule::new)
+            .collect::<Result<Vec<_>>>()?;
+        rules.append(&mut self.rules);
+        self.rules = rules;
+
+        for (extension, ExtensionRules { rules }) in file.extensions {
+            let rules = rules.into_iter().map(Rule::new).collect::<Result<_>>()?;
+            self.per_extension.insert(extension, rules);
+        }
+
+        Ok(self)
+    }
+
+    fn rules_for<'a>(&'a self, extension: &str) -> impl Iterator<Item = &'a Rule> {
+        self.per_extension
+            .get(extension)
+            .into_iter()
+            .flatten()
+            .chain(&self.rules)
+    }
+
+    /// Decide where `entry` gets installed, if at all
+    pub fn place(&self, entry: &Entry, context: &TemplateContext) -> Result<Option<Placement>> {
+        let path = Self::normalize(&entry.path);
+        if path.as_os_str().is_empty() {
+            return Ok(None);
+        }
+
+        let Some(rule) = self
+            .rules_for(context.extension)
+            .find(|rule| rule.is_match(&path))
+        else {
+            if entry.kind != EntryKind::Directory {
+                eprintln!(
+                    "{}: no install rule matches {}, leaving it out",
+                    context.extension,
+                    path.display()
+                );
+            }
+            return Ok(None);
+        };
+
+        let Some(dest) = &rule.spec.dest else {
+            return Ok(None);
+        };
+
+        let relative = if rule.spec.keep_subdirs {
+            path.strip_prefix(&rule.literal_prefix).unwrap_or(&path)
+        } else if entry.kind == EntryKind::Directory {
+            // Directories are created as needed by the files they hold
+            return Ok(None);
+        } else {
+            Path::new(path.file_name().unwrap_or_default())
+        };
+
+        let target = Path::new(&context.expand(dest, rule.spec.package)?).join(relative);
+        let mode = rule
+            .spec
+            .mode
+            .filter(|_| entry.kind == EntryKind::Regular)
+            .unwrap_or_else(|| Self::default_mode(entry));
+
+        Ok(Some(Placement {
+            target,
+            mode,
+            package: rule.spec.package,
+        }))
+    }
+
+    /// Strip `./` and similar from an archive path
+    fn normalize(path: &Path) -> PathBuf {
+        path.components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect()
+    }
+
+    /// Permissions for entries whose rule doesn't set any
+    fn default_mode(entry: &Entry) -> u32 {
+        match entry.kind {
+            EntryKind::Directory => 0o755,
+            EntryKind::Symlink => 0o777,
+            EntryKind::Regular if entry.is_executable() => 0o755,
+            EntryKind::Regular => 0o644,
+        }
+    }
+}
@@ -0,0 +1,949 @@
+use std::{
+    collections::BTreeSet,
+    fmt::{Display, Write},
+    ops::Not,
+    path::Path,
+    str::FromStr,
+};
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    deb_reader::{DataEntry, DataEntryKind, DebFile},
+    dependencies,
+    extension_control::ControlSpec,
+    upgrade_graph::UpgradeGraph,
+    Result,
+};
+
+/// Where the files of an extension package may be installed
+const ALLOWED_PREFIXES: [&str; 5] = [
+    "/usr/lib/postgresql/",
+    "/usr/share/postgresql/",
+    "/usr/include/postgresql/",
+    "/usr/share/doc/",
+    "/usr/lib/debug/",
+];
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// The SARIF `level` of this severity
+    fn sarif_level(self) -> &'static str {
+        match self {
+            Severity::Info => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(severity: &str) -> std::result::Result<Self, Self::Err> {
+        match severity {
+            "info" => Ok(Self::Info),
+            "warning" => Ok(Self::Warning),
+            "error" => Ok(Self::Error),
+            other => Err(format!(
+                "unknown severity `{other}`, expected one of info, warning or error"
+            )),
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// A check run over every package. Codes are stable: never reuse or renumber them.
+pub struct Check {
+    pub code: &'static str,
+    pub name: &'static str,
+    pub severity: Severity,
+    pub description: &'static str,
+}
+
+pub const MISSING_MAINTAINER: Check = Check {
+    code: "TP001",
+    name: "missing-maintainer",
+    severity: Severity::Warning,
+    description: "The control file has no Maintainer field",
+};
+
+pub const MISSING_COPYRIGHT: Check = Check {
+    code: "TP002",
+    name: "missing-copyright",
+    severity: Severity::Error,
+    description: "The package does not ship /usr/share/doc/<package>/copyright",
+};
+
+pub const FILE_OUTSIDE_ALLOWED_PREFIXES: Check = Check {
+    code: "TP003",
+    name: "file-outside-allowed-prefixes",
+    severity: Severity::Warning,
+    description: "A file is installed outside of the PostgreSQL and documentation directories",
+};
+
+pub const WORLD_WRITABLE: Check = Check {
+    code: "TP004",
+    name: "world-writable",
+    severity: Severity::Error,
+    description: "A file or directory is writable by everyone",
+};
+
+pub const ELF_WITHOUT_DEPENDS: Check = Check {
+    code: "TP005",
+    name: "elf-without-depends",
+    severity: Severity::Warning,
+    description: "The package ships ELF files needing libraries, but only depends on PostgreSQL",
+};
+
+pub const MISSING_MODULE_MAGIC: Check = Check {
+    code: "TP006",
+    name: "missing-module-magic",
+    severity: Severity::Error,
+    description: "A PostgreSQL module does not export Pg_magic_func (PG_MODULE_MAGIC)",
+};
+
+pub const MODULE_PATHNAME_NOT_SHIPPED: Check = Check {
+    code: "TP007",
+    name: "module-pathname-not-shipped",
+    severity: Severity::Error,
+    description: "An extension's module_pathname points to a library the package does not ship",
+};
+
+pub const DEFAULT_VERSION_NOT_INSTALLABLE: Check = Check {
+    code: "TP008",
+    name: "default-version-not-installable",
+    severity: Severity::Error,
+    description: "No install script or chain of upgrade scripts leads to the default_version",
+};
+
+pub const INVALID_CONTROL_FILE: Check = Check {
+    code: "TP009",
+    name: "invalid-control-file",
+    severity: Severity::Error,
+    description: "An extension's control file can't be parsed",
+};
+
+pub const UNREACHABLE_VERSION: Check = Check {
+    code: "TP010",
+    name: "unreachable-version",
+    severity: Severity::Warning,
+    description: "An upgrade script mentions a version that no install script leads to",
+};
+
+pub const DEAD_END_UPGRADE_PATH: Check = Check {
+    code: "TP011",
+    name: "dead-end-upgrade-path",
+    severity: Severity::Warning,
+    description: "No chain of upgrade scripts leads from a version to the default_version",
+};
+
+pub const CHECKS: [&Check; 11] = [
+    &MISSING_MAINTAINER,
+    &MISSING_COPYRIGHT,
+    &FILE_OUTSIDE_ALLOWED_PREFIXES,
+    &WORLD_WRITABLE,
+    &ELF_WITHOUT_DEPENDS,
+    &MISSING_MODULE_MAGIC,
+    &MODULE_PATHNAME_NOT_SHIPPED,
+    &DEFAULT_VERSION_NOT_INSTALLABLE,
+    &INVALID_CONTROL_FILE,
+    &UNREACHABLE_VERSION,
+    &DEAD_END_UPGRADE_PATH,
+];
+
+/// A problem found in a package
+#[derive(Serialize, Clone, Debug)]
+pub struct Finding {
+    pub code: &'static str,
+    pub name: &'static str,
+    pub severity: Severity,
+    /// The .deb the problem was found in
+    pub file: String,
+    pub package: String,
+    pub message: String,
+    /// The installed path the problem is about, if any
+    pub path: Option<String>,
+}
+
+/// How findings are printed
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum LintFormat {
+    #[default]
+    Text,
+    Json,
+    Sarif,
+}
+
+impl FromStr for LintFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> std::result::Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "sarif" => Ok(Self::Sarif),
+            other => Err(format!(
+                "unknown format `{other}`, expected one of text, json or sarif"
+            )),
+        }
+    }
+}
+
+/// Runs the checks over a single package
+struct Linter<'a> {
+    file: &'a Path,
+    deb: &'a DebFile,
+    findings: Vec<Finding>,
+}
+
+impl<'a> Linter<'a> {
+    fn report(&mut self, check: &Check, message: String, path: Option<&str>) {
+        self.findings.push(Finding {
+            code: check.code,
+            name: check.name,
+            severity: check.severity,
+            file: self.file.display().to_string(),
+            package: self.deb.control.package.clone(),
+            message,
+            path: path.map(str::to_owned),
+        });
+    }
+
+    /// `./usr/lib/foo.so` → `/usr/lib/foo.so`
+    fn install_path(entry: &DataEntry) -> &str {
+        entry.path.trim_start_matches('.')
+    }
+
+    fn is_elf(entry: &DataEntry) -> bool {
+        entry.kind == DataEntryKind::File && entry.contents.starts_with(b"\x7fELF")
+    }
+
+    fn files(&self) -> impl Iterator<Item = &'a DataEntry> {
+        self.deb
+            .entries
+            .iter()
+            .filter(|entry| entry.kind != DataEntryKind::Directory)
+    }
+
+    fn check_maintainer(&mut self) {
+        if self.deb.control.maintainer.is_none() {
+            self.report(
+                &MISSING_MAINTAINER,
+                "control file has no Maintainer".into(),
+                None,
+            );
+        }
+    }
+
+    fn check_copyright(&mut self) {
+        let copyright = format!("/usr/share/doc/{}/copyright", self.deb.control.package);
+        let has_copyright = self
+            .files()
+            .any(|entry| Self::install_path(entry) == copyright);
+
+        if has_copyright.not() {
+            self.report(&MISSING_COPYRIGHT, format!("{copyright} is missing"), None);
+        }
+    }
+
+    fn check_paths_and_modes(&mut self) {
+        for entry in &self.deb.entries {
+            let path = Self::install_path(entry);
+
+            let is_allowed = ALLOWED_PREFIXES.iter().any(|prefix| {
+                path.starts_with(prefix)
+                    // Ancestors of the allowed directories, such as `/usr/lib`
+                    || (entry.kind == DataEntryKind::Directory
+                        && prefix.starts_with(&format!("{}/", path.trim_end_matches('/'))))
+            });
+            if is_allowed.not() {
+                self.report(
+                    &FILE_OUTSIDE_ALLOWED_PREFIXES,
+                    format!("{path} is outside of the allowed directories"),
+                    Some(path),
+                );
+            }
+
+            if entry.kind != DataEntryKind::Symlink && entry.mode & 0o002 != 0 {
+                self.report(
+                    &WORLD_WRITABLE,
+                    format!("{path} has mode {:o}", entry.mode),
+                    Some(path),
+                );
+            }
+        }
+    }
+
+    /// Whether `relation` can only be satisfied by PostgreSQL itself or libc, e.g. `postgresql-16`
+    fn is_postgresql_or_libc(relation: &str) -> bool {
+        relation.split('|').all(|alternative| {
+            let package = alternative
+                .split(|ch: char| ch.is_whitespace() || ch == '(')
+                .find(|name| name.is_empty().not())
+                .unwrap_or_default();
+            let pg_major = package.strip_prefix("postgresql-");
+
+            package == "libc6"
+                || pg_major.is_some_and(|pg_major| {
+                    pg_major.is_empty().not() && pg_major.chars().all(|ch| ch.is_ascii_digit())
+                })
+        })
+    }
+
+    fn check_elf_depends(&mut self) {
+        let depends_on_libraries = self
+            .deb
+            .control
+            .depends
+            .iter()
+            .any(|relation| Self::is_postgresql_or_libc(relation).not());
+        if depends_on_libraries {
+            return;
+        }
+
+        // Libraries the package ships itself need no Depends
+        let shipped: BTreeSet<&str> = self
+            .files()
+            .filter_map(|entry| Self::install_path(entry).rsplit('/').next())
+            .collect();
+
+        for entry in self.files().filter(|entry| Self::is_elf(entry)) {
+            let Ok(elf) = goblin::elf::Elf::parse(&entry.contents) else {
+                continue;
+            };
+            let needed: Vec<_> = elf
+                .libraries
+                .iter()
+                .filter(|soname| {
+                    dependencies::is_libc_soname(soname).not() && shipped.contains(*soname).not()
+                })
+                .copied()
+                .collect();
+
+            if needed.is_empty().not() {
+                let path = Self::install_path(entry);
+                self.report(
+                    &ELF_WITHOUT_DEPENDS,
+                    format!(
+                        "{path} needs {}, yet the package only depends on PostgreSQL",
+                        needed.join(", ")
+                    ),
+                    Some(path),
+                );
+            }
+        }
+    }
+
+    /// Shared objects in a PostgreSQL `lib` directory are modules the server loads
+    fn is_module(entry: &DataEntry) -> bool {
+        let path = Self::install_path(entry);
+
+        Self::is_elf(entry)
+            && path.starts_with("/usr/lib/postgresql/")
+            && path.ends_with(".so")
+            && path.contains("/bitcode/").not()
+    }
+
+    fn check_module_magic(&mut self) {
+        for entry in self.files().filter(|entry| Self::is_module(entry)) {
+            let Ok(elf) = goblin::elf::Elf::parse(&entry.contents) else {
+                continue;
+            };
+
+            let has_magic = elf.dynsyms.iter().any(|symbol| {
+                symbol.st_shndx != 0
+                    && elf.dynstrtab.get_at(symbol.st_name) == Some("Pg_magic_func")
+            });
+
+            if has_magic.not() {
+                let path = Self::install_path(entry);
+                self.report(
+                    &MISSING_MODULE_MAGIC,
+                    format!("{path} does not export Pg_magic_func, so PostgreSQL won't load it"),
+                    Some(path),
+                );
+            }
+        }
+    }
+
+    fn check_control_files(&mut self) {
+        let files: Vec<_> = self.files().collect();
+
+        // Secondary control files such as `foo--1.0.control` only override a few settings
+        let control_files = files.iter().filter(|entry| {
+            let path = Self::install_path(entry);
+            let file_name = path.rsplit('/').next().unwrap_or_default();
+
+            path.starts_with("/usr/share/postgresql/")
+                && path.ends_with(".control")
+                && file_name.contains("--").not()
+        });
+
+        for control_file in control_files {
+            let path = Self::install_path(control_file);
+            let extension = Path::new(path)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default();
+            let spec = match ControlSpec::parse(
+                extension,
+                &String::from_utf8_lossy(&control_file.contents),
+            ) {
+                Result::Ok(spec) => spec,
+                Err(err) => {
+                    self.report(&INVALID_CONTROL_FILE, format!("{err:#}"), Some(path));
+                    continue;
+                }
+            };
+
+            if let Some(library) = spec.module_library() {
+                let is_shipped = files.iter().any(|entry| {
+                    Self::install_path(entry)
+                        .rsplit('/')
+                        .next()
+                        .is_some_and(|file_name| file_name == format!("{library}.so"))
+                });
+
+                if is_shipped.not() {
+                    self.report(
+                        &MODULE_PATHNAME_NOT_SHIPPED,
+                        format!(
+                            "module_pathname is {}, but {library}.so is not shipped",
+                            spec.module_pathname.as_deref().unwrap_or_default()
+                        ),
+                        Some(path),
+                    );
+                }
+            }
+
+            if let Some(default_version) = &spec.default_version {
+                let scripts = files.iter().filter_map(|entry| {
+                    let file_name = Self::install_path(entry).rsplit('/').next()?;
+                    file_name
+                        .strip_prefix(extension)?
+                        .strip_prefix("--")?
+                        .strip_suffix(".sql")
+                });
+
+                let report =
+                    UpgradeGraph::from_script_names(scripts).analyze(extension, default_version);
+
+                if report.installable.not() {
+                    self.report(
+                        &DEFAULT_VERSION_NOT_INSTALLABLE,
+                        format!("no SQL scripts install {extension} {default_version}"),
+                        Some(path),
+                    );
+                }
+                if report.unreachable.is_empty().not() {
+                    self.report(
+                        &UNREACHABLE_VERSION,
+                        format!(
+                            "no install script leads to {}",
+                            report.unreachable.join(", ")
+                        ),
+                        Some(path),
+                    );
+                }
+                if report.dead_ends.is_empty().not() {
+                    self.report(
+                        &DEAD_END_UPGRADE_PATH,
+                        format!(
+                            "{} can't be updated to {default_version}",
+                            report.dead_ends.join(", ")
+                        ),
+                        Some(path),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Run every check over the package at `file`
+pub fn lint(file: &Path, deb: &DebFile) -> Vec<Finding> {
+    let mut linter = Linter {
+        file,
+        deb,
+        findings: Vec::new(),
+    };
+
+    linter.check_maintainer();
+    linter.check_copyright();
+    linter.check_paths_and_modes();
+    linter.check_elf_depends();
+    linter.check_module_magic();
+    linter.check_control_files();
+
+    linter.findings
+}
+
+/// Read and lint every given package
+pub fn lint_files(files: &[impl AsRef<Path>]) -> Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+
+    for file in files {
+        let file = file.as_ref();
+        let deb = DebFile::read(file)?;
+
+        findings.extend(lint(file, &deb));
+    }
+
+    Ok(findings)
+}
+
+/// Lint freshly built packages, failing if any has errors
+pub fn gate(files: &[impl AsRef<Path>]) -> Result {
+    let findings = lint_files(files)?;
+    eprint!("{}", render_text(&findings)?);
+
+    let errors = findings
+        .iter()
+        .filter(|finding| finding.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        anyhow::bail!("Linting found {errors} error(s)");
+    }
+
+    Ok(())
+}
+
+pub fn render(findings: &[Finding], format: LintFormat) -> Result<String> {
+    match format {
+        LintFormat::Text => render_text(findings),
+        LintFormat::Json => Ok(serde_json::to_string_pretty(findings)?),
+        LintFormat::Sarif => render_sarif(findings),
+    }
+}
+
+fn render_text(findings: &[Finding]) -> Result<String> {
+    let mut text = String::with_capacity(128 * findings.len());
+
+    for finding in findings {
+        writeln!(
+            text,
+            "{}: {} {} {}: {}",
+            finding.package, finding.severity, finding.code, finding.name, finding.message
+        )?;
+    }
+
+    Ok(text)
+}
+
+/// Render findings as a SARIF 2.1.0 log, as consumed by code scanning tools
+///
+/// Docs.: <https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html>
+fn render_sarif(findings: &[Finding]) -> Result<String> {
+    let rules: Vec<_> = CHECKS
+        .iter()
+        .map(|check| {
+            json!({
+                "id": check.code,
+                "name": check.name,
+                "shortDescription": { "text": check.description },
+                "defaultConfiguration": { "level": check.severity.sarif_level() },
+            })
+        })
+        .collect();
+
+    let results: Vec<_> = findings
+        .iter()
+        .map(|finding| {
+            let mut location = json!({
+                "physicalLocation": { "artifactLocation": { "uri": finding.file } },
+            });
+            if let Some(path) = &finding.path {
+                location["logicalLocations"] = json!([{ "fullyQualifiedName": path }]);
+            }
+
+            json!({
+                "ruleId": finding.code,
+                "level": finding.severity.sarif_level(),
+                "message": { "text": format!("{}: {}", finding.package, finding.message) },
+                "locations": [location],
+            })
+        })
+        .collect();
+
+    let log = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }],
+    });
+
+    Ok(serde_json::to_string_pretty(&log)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ops::Not, path::Path};
+
+    use serde_json::Value;
+
+    use crate::{
+        compression::Compression,
+        control::ControlFields,
+        deb_reader::{DataEntry, DataEntryKind, DebFile},
+    };
+
+    use super::{lint, render, Finding, LintFormat, CHECKS};
+
+    /// Built by `tests/fixtures/build.sh`: exports `Pg_magic_func` and needs `libcrypt.so.1`
+    const MODULE: &[u8] = include_bytes!("../tests/fixtures/module.so");
+    /// Needs nothing beyond libc and libm, and lacks `Pg_magic_func`
+    const NOT_A_MODULE: &[u8] = include_bytes!("../tests/fixtures/libhardened.so");
+
+    const EXTENSION_DIR: &str = "./usr/share/postgresql/16/extension";
+
+    fn entry(path: &str, kind: DataEntryKind, mode: u32, contents: &[u8]) -> DataEntry {
+        DataEntry {
+            path: path.to_owned(),
+            kind,
+            mode,
+            size: contents.len() as u64,
+            mtime: 0,
+            owner: "root".into(),
+            group: "root".into(),
+            link_target: None,
+            contents: contents.to_vec(),
+        }
+    }
+
+    fn file(path: &str, contents: &[u8]) -> DataEntry {
+        entry(path, DataEntryKind::File, 0o644, contents)
+    }
+
+    /// A package none of the checks find anything wrong with
+    fn deb() -> DebFile {
+        let entries = vec![
+            entry("./", DataEntryKind::Directory, 0o755, b""),
+            entry("./usr", DataEntryKind::Directory, 0o755, b""),
+            entry("./usr/lib", DataEntryKind::Directory, 0o755, b""),
+            file("./usr/lib/postgresql/16/lib/myext.so", MODULE),
+            file(
+                &format!("{EXTENSION_DIR}/myext.control"),
+                b"default_version = '1.1'\nmodule_pathname = '$libdir/myext'\n",
+            ),
+            file(&format!("{EXTENSION_DIR}/myext--1.0.sql"), b""),
+            file(&format!("{EXTENSION_DIR}/myext--1.0--1.1.sql"), b""),
+            // Secondary control files aren't checked on their own
+            file(&format!("{EXTENSION_DIR}/myext--1.0.control"), b"?"),
+            file("./usr/share/doc/postgresql-16-myext/copyright", b""),
+        ];
+
+        DebFile {
+            format_version: "2.0".into(),
+            control_compression: Compression::Gzip,
+            data_compression: Compression::Gzip,
+            control: ControlFields {
+                package: "postgresql-16-myext".into(),
+                architecture: "amd64".into(),
+                version: "1.1".into(),
+                maintainer: Some("Jane Doe <jane@example.com>".into()),
+                description: "My extension".into(),
+                depends: vec!["postgresql-16".into(), "libcrypt1 (>= 1:4.4)".into()],
+                ..ControlFields::default()
+            },
+            control_files: vec!["control".into()],
+            entries,
+            hardening: Default::default(),
+        }
+    }
+
+    fn findings(deb: &DebFile) -> Vec<Finding> {
+        lint(Path::new("postgresql-16-myext_1.1_amd64.deb"), deb)
+    }
+
+    fn codes(deb: &DebFile) -> Vec<&'static str> {
+        findings(deb).iter().map(|finding| finding.code).collect()
+    }
+
+    fn replace(deb: &mut DebFile, path: &str, contents: &[u8]) {
+        let entry = deb
+            .entries
+            .iter_mut()
+            .find(|entry| entry.path == path)
+            .unwrap();
+        entry.contents = contents.to_vec();
+    }
+
+    #[test]
+    fn clean() {
+        assert!(findings(&deb()).is_empty(), "{:?}", findings(&deb()));
+    }
+
+    #[test]
+    fn missing_maintainer() {
+        let mut deb = deb();
+        deb.control.maintainer = None;
+
+        assert_eq!(codes(&deb), ["TP001"]);
+    }
+
+    #[test]
+    fn missing_copyright() {
+        let mut deb = deb();
+        deb.entries
+            .retain(|entry| entry.path.ends_with("/copyright").not());
+
+        assert_eq!(codes(&deb), ["TP002"]);
+    }
+
+    #[test]
+    fn outside_allowed_prefixes() {
+        let mut deb = deb();
+        deb.entries
+            .push(file("./etc/postgresql/16/main/conf.d/myext.conf", b""));
+        deb.entries.push(file("./usr/local/lib/libfoo.so", b""));
+
+        let findings = findings(&deb);
+        let paths: Vec<_> = findings
+            .iter()
+            .map(|finding| (finding.code, finding.path.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("TP003", "/etc/postgresql/16/main/conf.d/myext.conf"),
+                ("TP003", "/usr/local/lib/libfoo.so")
+            ]
+        );
+    }
+
+    #[test]
+    fn world_writable() {
+        let mut deb = deb();
+        deb.entries[3].mode = 0o666;
+        let mut link = entry(
+            "./usr/lib/postgresql/16/lib/myext.so.1",
+            DataEntryKind::Symlink,
+            0o777,
+            b"",
+        );
+        link.link_target = Some("myext.so".into());
+        deb.entries.push(link);
+
+        let findings = findings(&deb);
+        assert_eq!(findings.len(), 1, "{findings:?}");
+        assert_eq!(findings[0].code, "TP004");
+        assert_eq!(
+            findings[0].message,
+            "/usr/lib/postgresql/16/lib/myext.so has mode 666"
+        );
+    }
+
+    #[test]
+    fn elf_without_depends() {
+        let mut deb = deb();
+        deb.control.depends = vec!["postgresql-16".into(), "libc6 (>= 2.34)".into()];
+
+        let findings = findings(&deb);
+        assert_eq!(findings.len(), 1, "{findings:?}");
+        assert_eq!(findings[0].code, "TP005");
+        assert_eq!(
+            findings[0].message,
+            "/usr/lib/postgresql/16/lib/myext.so needs libcrypt.so.1, yet the package only \
+             depends on PostgreSQL"
+        );
+
+        // Shipping the library it needs is just as good
+        deb.entries
+            .push(file("./usr/lib/postgresql/16/lib/libcrypt.so.1", b""));
+        assert!(codes(&deb).is_empty());
+    }
+
+    #[test]
+    fn libc_needs_no_depends() {
+        let mut deb = deb();
+        deb.control.depends = vec!["postgresql-16".into()];
+        deb.entries = vec![
+            file("./usr/lib/postgresql/16/bin/myext_tool", NOT_A_MODULE),
+            file("./usr/share/doc/postgresql-16-myext/copyright", b""),
+        ];
+
+        assert!(findings(&deb).is_empty(), "{:?}", findings(&deb));
+    }
+
+    #[test]
+    fn missing_module_magic() {
+        let mut deb = deb();
+        deb.entries
+            .push(file("./usr/lib/postgresql/16/lib/helper.so", NOT_A_MODULE));
+        // Only modules in PostgreSQL's lib directory are loaded by the server
+        deb.entries.push(file(
+            "./usr/lib/postgresql/16/lib/bitcode/helper.so",
+            NOT_A_MODULE,
+        ));
+
+        assert_eq!(codes(&deb), ["TP006"]);
+    }
+
+    #[test]
+    fn module_pathname_not_shipped() {
+        let mut deb = deb();
+        replace(
+            &mut deb,
+            &format!("{EXTENSION_DIR}/myext.control"),
+            b"default_version = '1.1'\nmodule_pathname = '$libdir/missing'\n",
+        );
+
+        assert_eq!(codes(&deb), ["TP007"]);
+    }
+
+    #[test]
+    fn default_version_not_installable() {
+        let mut deb = deb();
+        replace(
+            &mut deb,
+            &format!("{EXTENSION_DIR}/myext.control"),
+            b"default_version = '1.2'\n",
+        );
+
+        assert_eq!(codes(&deb), ["TP008", "TP011"]);
+    }
+
+    #[test]
+    fn invalid_control_file() {
+        let mut deb = deb();
+        replace(
+            &mut deb,
+            &format!("{EXTENSION_DIR}/myext.control"),
+            b"default_version = '1.1\n",
+        );
+
+        let findings = findings(&deb);
+        assert_eq!(findings.len(), 1, "{findings:?}");
+        assert_eq!(findings[0].code, "TP009");
+        assert_eq!(
+            findings[0].path.as_deref(),
+            Some("/usr/share/postgresql/16/extension/myext.control")
+        );
+    }
+
+    #[test]
+    fn unreachable_version() {
+        let mut deb = deb();
+        deb.entries
+            .push(file(&format!("{EXTENSION_DIR}/myext--0.9--1.0.sql"), b""));
+
+        let findings = findings(&deb);
+        assert_eq!(findings.len(), 1, "{findings:?}");
+        assert_eq!(findings[0].code, "TP010");
+        assert_eq!(findings[0].message, "no install script leads to 0.9");
+    }
+
+    #[test]
+    fn dead_end_upgrade_path() {
+        let mut deb = deb();
+        deb.entries
+            .push(file(&format!("{EXTENSION_DIR}/myext--1.1--1.2.sql"), b""));
+
+        let findings = findings(&deb);
+        assert_eq!(findings.len(), 1, "{findings:?}");
+        assert_eq!(findings[0].code, "TP011");
+        assert_eq!(findings[0].message, "1.2 can't be updated to 1.1");
+    }
+
+    #[test]
+    fn json() {
+        let mut deb = deb();
+        deb.control.maintainer = None;
+        deb.entries[3].mode = 0o646;
+
+        let json: Value =
+            serde_json::from_str(&render(&findings(&deb), LintFormat::Json).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {
+                    "code": "TP001",
+                    "name": "missing-maintainer",
+                    "severity": "warning",
+                    "file": "postgresql-16-myext_1.1_amd64.deb",
+                    "package": "postgresql-16-myext",
+                    "message": "control file has no Maintainer",
+                    "path": null,
+                },
+                {
+                    "code": "TP004",
+                    "name": "world-writable",
+                    "severity": "error",
+                    "file": "postgresql-16-myext_1.1_amd64.deb",
+                    "package": "postgresql-16-myext",
+                    "message": "/usr/lib/postgresql/16/lib/myext.so has mode 646",
+                    "path": "/usr/lib/postgresql/16/lib/myext.so",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn sarif() {
+        let mut deb = deb();
+        deb.entries[3].mode = 0o646;
+
+        let sarif: Value =
+            serde_json::from_str(&render(&findings(&deb), LintFormat::Sarif).unwrap()).unwrap();
+        assert_eq!(sarif["version"], "2.1.0");
+
+        let run = &sarif["runs"][0];
+        let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+        assert_eq!(rules.len(), CHECKS.len());
+        assert_eq!(
+            rules[3],
+            serde_json::json!({
+                "id": "TP004",
+                "name": "world-writable",
+                "shortDescription": { "text": "A file or directory is writable by everyone" },
+                "defaultConfiguration": { "level": "error" },
+            })
+        );
+        assert_eq!(
+            run["results"],
+            serde_json::json!([{
+                "ruleId": "TP004",
+                "level": "error",
+                "message": {
+                    "text": "postgresql-16-myext: /usr/lib/postgresql/16/lib/myext.so has mode 646"
+                },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": "postgresql-16-myext_1.1_amd64.deb" }
+                    },
+                    "logicalLocations": [
+                        { "fullyQualifiedName": "/usr/lib/postgresql/16/lib/myext.so" }
+                    ],
+                }],
+            }])
+        );
+    }
+}
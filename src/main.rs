@@ -1,16 +1,42 @@
+mod carry_forward;
+mod changelog;
 mod cli;
 mod client;
+mod compression;
+mod control;
+mod copyright;
 mod deb_packager;
+mod deb_reader;
 mod dependencies;
+mod dependency_graph;
+mod dlopen;
+mod extension_control;
+mod hardening;
+mod layout;
+mod lint;
+mod manifest;
+mod mappings;
+mod mirror;
+mod needs_index;
+mod packages_index;
+mod preload;
+mod report;
+mod split;
+mod strip;
 mod unarchiver;
+mod upgrade_graph;
 mod utils;
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::ops::Not;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{Context, Ok};
-use cli::{PackageAll, PackageOne};
+use cli::{
+    Inspect, Lint, PackageAll, PackageOne, Report, Reports, ShowSharedObjects, UnknownSonames,
+    WhoNeeds,
+};
 use client::Extension;
 use dependencies::FetchData;
 use once_cell::sync::Lazy;
@@ -19,8 +45,16 @@ use tempfile::TempDir;
 
 use crate::cli::Subcommands;
 use crate::client::Client;
-use crate::deb_packager::DebPackager;
+use crate::deb_packager::{BuildOptions, DebPackager};
+use crate::deb_reader::DebFile;
 use crate::dependencies::Dependencies;
+use crate::dependency_graph::{DependencyGraph, GraphFormat};
+use crate::hardening::Hardening;
+use crate::mappings::SonameMappings;
+use crate::needs_index::{IndexEntry, NeedsIndex};
+use crate::packages_index::PackagesIndex;
+use crate::preload::{Preload, PreloadRequirement};
+use crate::report::UnknownSonameReport;
 
 pub type Result<T = ()> = anyhow::Result<T>;
 
@@ -37,31 +71,117 @@ async fn package_extension(
     trunk_project_name: String,
     export_dir: PathBuf,
     maybe_file: Option<PathBuf>,
+    all_options: Vec<BuildOptions>,
 ) -> Result {
     std::env::set_current_dir(&*TEMP_DIR)?;
 
-    let data_fetched = if let Some(file) = maybe_file {
-        fetch_from_local_file(base_url, &file, &trunk_project_name).await?
-    } else {
-        fetch_archive_from_registry(base_url, &trunk_project_name).await?
-    };
+    if maybe_file.is_some() && all_options.len() > 1 {
+        anyhow::bail!(
+            "--file holds a single archive, so it can't be built for several PostgreSQL versions"
+        );
+    }
+
+    let mut outcomes = Vec::with_capacity(all_options.len());
 
-    let archive_written = DebPackager::build_deb(data_fetched, &export_dir).await?;
-    println!("Wrote archive at {}", archive_written.display());
+    for options in &all_options {
+        let work = async {
+            let data_fetched = if let Some(file) = &maybe_file {
+                fetch_from_local_file(base_url.clone(), file, &trunk_project_name, options).await?
+            } else {
+                fetch_archive_from_registry(base_url.clone(), &trunk_project_name, options).await?
+            };
+
+            let archives_written =
+                DebPackager::build_deb(data_fetched, &export_dir, options).await?;
+            if options.lint {
+                lint::gate(&archives_written)?;
+            }
+
+            Ok(archives_written)
+        };
+
+        let outcome = work.await;
+        for archive_written in outcome.iter().flatten() {
+            println!("Wrote archive at {}", archive_written.display());
+        }
+        outcomes.push((trunk_project_name.clone(), options.pg_major, outcome));
+    }
+
+    let failures = print_summary(outcomes);
+    if failures > 0 {
+        anyhow::bail!("{failures} package(s) of {trunk_project_name} failed to build");
+    }
 
     Ok(())
 }
 
+/// Print which packages were built for every extension and PostgreSQL version, followed by the
+/// errors of those which weren't. Returns how many failed.
+fn print_summary(outcomes: Vec<(String, u16, Result<Vec<PathBuf>>)>) -> usize {
+    let pg_versions: BTreeSet<u16> = outcomes.iter().map(|(_, pg_major, _)| *pg_major).collect();
+    let mut matrix: BTreeMap<&str, BTreeMap<u16, bool>> = BTreeMap::new();
+    let mut failures = Vec::new();
+
+    for (extension, pg_major, outcome) in &outcomes {
+        matrix
+            .entry(extension)
+            .or_default()
+            .insert(*pg_major, outcome.is_ok());
+
+        if let Err(err) = outcome {
+            failures.push(format!("{extension} (PostgreSQL {pg_major}): {err:#}"));
+        }
+    }
+
+    let name_width = matrix
+        .keys()
+        .map(|name| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(9);
+
+    print!("{:name_width$}", "extension");
+    for pg_major in &pg_versions {
+        print!("  PG{pg_major:<3}");
+    }
+    println!();
+
+    for (extension, built) in &matrix {
+        print!("{extension:name_width$}");
+        for pg_major in &pg_versions {
+            match built.get(pg_major) {
+                Some(true) => print!("  {}", "ok   ".green()),
+                Some(false) => print!("  {}", "FAIL ".red()),
+                None => print!("  -    "),
+            }
+        }
+        println!();
+    }
+
+    for failure in &failures {
+        eprintln!("Err: {failure}");
+    }
+
+    failures.len()
+}
+
 async fn fetch_from_local_file(
     base_url: String,
     archive_path: &Path,
     trunk_project_name: &str,
+    options: &BuildOptions,
 ) -> Result<FetchData> {
-    let (_client, extension) = fetch_extension(base_url, trunk_project_name).await?;
+    let (client, extension) = fetch_extension(base_url, trunk_project_name).await?;
+    let versions = client.version_history(trunk_project_name).await;
 
     let archive = std::fs::read(archive_path).with_context(|| "Failed to read supplied archive")?;
 
-    Dependencies::decompress_archive(extension, &archive)
+    let mut data = Dependencies::decompress_archive(extension, versions, &archive)?;
+    if let Some(carried) = options.carry_forward {
+        carry_forward::fetch_history(&client, &mut data, options.pg_major, carried).await;
+    }
+
+    Ok(data)
 }
 
 async fn fetch_extension(
@@ -88,21 +208,31 @@ async fn fetch_extension(
 async fn fetch_archive_from_registry(
     base_url: String,
     trunk_project_name: &str,
+    options: &BuildOptions,
 ) -> Result<FetchData> {
     let (client, extension) = fetch_extension(base_url, trunk_project_name).await?;
 
-    Dependencies::fetch_from_archive(extension, client)
+    let mut data = Dependencies::fetch_from_archive(extension, client.clone(), options.pg_major)
         .await
-        .with_context(|| "Failed to fetch archive")
+        .with_context(|| "Failed to fetch archive")?;
+    if let Some(carried) = options.carry_forward {
+        carry_forward::fetch_history(&client, &mut data, options.pg_major, carried).await;
+    }
+
+    Ok(data)
 }
 
-async fn package_all_extensions(base_url: String, export_dir: PathBuf) -> Result {
+async fn package_all_extensions(
+    base_url: String,
+    export_dir: PathBuf,
+    all_options: Vec<BuildOptions>,
+) -> Result {
     let export_dir: Arc<Path> = Arc::from(export_dir);
     let client = Client::new(base_url);
     std::env::set_current_dir(&*TEMP_DIR)?;
 
     let extensions = client.fetch_extensions().await?;
-    let mut handles = Vec::with_capacity(extensions.len());
+    let mut handles = Vec::with_capacity(extensions.len() * all_options.len());
 
     println!(
         "[{}] Loaded {} extensions.",
@@ -111,32 +241,366 @@ async fn package_all_extensions(base_url: String, export_dir: PathBuf) -> Result
     );
 
     for extension in extensions {
-        // Copies for the Tokio Task
-        let my_client = client.clone();
-        let my_export_dir = export_dir.clone();
+        for options in &all_options {
+            // Copies for the Tokio Task
+            let my_extension = extension.clone();
+            let my_client = client.clone();
+            let my_export_dir = export_dir.clone();
+            let my_options = options.clone();
+
+            let work = async move {
+                let mut data_fetched = Dependencies::fetch_from_archive(
+                    my_extension,
+                    my_client.clone(),
+                    my_options.pg_major,
+                )
+                .await?;
+                if let Some(carried) = my_options.carry_forward {
+                    carry_forward::fetch_history(
+                        &my_client,
+                        &mut data_fetched,
+                        my_options.pg_major,
+                        carried,
+                    )
+                    .await;
+                }
+
+                let archives_written =
+                    DebPackager::build_deb(data_fetched, my_export_dir, &my_options).await?;
+                if my_options.lint {
+                    lint::gate(&archives_written)?;
+                }
+                for archive_written in &archives_written {
+                    println!("Wrote archive at {}", archive_written.display());
+                }
+
+                Ok(archives_written)
+            };
+
+            handles.push((extension.name.clone(), options.pg_major, tokio::spawn(work)));
+        }
+    }
 
-        let work = async move {
-            let data_fetched = Dependencies::fetch_from_archive(extension, my_client).await?;
+    let mut outcomes = Vec::with_capacity(handles.len());
 
-            let archive_written = DebPackager::build_deb(data_fetched, my_export_dir).await?;
-            println!("Wrote archive at {}", archive_written.display());
+    for (extension, pg_major, handle) in handles {
+        outcomes.push((extension, pg_major, handle.await?));
+    }
+
+    let failures = print_summary(outcomes);
+    if failures > 0 {
+        anyhow::bail!("{failures} package(s) failed to build");
+    }
+
+    Ok(())
+}
+
+/// Print the shared objects of every extension, along with what they depend on and how they're
+/// hardened, or only their dependency graphs as JSON or DOT
+async fn show_shared_objects(base_url: String, pg_version: u16, format: GraphFormat) -> Result {
+    let client = Client::new(base_url);
+    let extensions = client.fetch_extensions().await?;
+    let mut handles = Vec::with_capacity(extensions.len());
+
+    for extension in extensions {
+        let name = extension.name.clone();
+        let work = Dependencies::fetch_from_archive(extension, client.clone(), pg_version);
+
+        handles.push((name, tokio::spawn(work)));
+    }
+
+    // Awaited in order so that the output doesn't depend on which download finishes first
+    let mut fetched = Vec::with_capacity(handles.len());
+    for (name, handle) in handles {
+        match handle.await? {
+            Result::Ok(data) if format == GraphFormat::Text => show_extension(&data)?,
+            Result::Ok(data) => fetched.push(data),
+            Err(err) => eprintln!("Err: {name}: {err:#}"),
+        }
+    }
+
+    let graphs: Vec<_> = fetched
+        .iter()
+        .map(|data| DependencyGraph {
+            extension: &data.extension.name,
+            version: &data.extension.latest_version,
+            objects: &data.dependencies.objects,
+        })
+        .collect();
+
+    match format {
+        GraphFormat::Text => {}
+        GraphFormat::Json => println!("{}", serde_json::to_string_pretty(&graphs)?),
+        GraphFormat::Dot => print!("{}", DependencyGraph::render_dot(&graphs)?),
+    }
+
+    Ok(())
+}
 
-            Ok(())
+fn show_extension(data: &FetchData) -> Result {
+    println!(
+        "{} {}",
+        data.extension.name.blue(),
+        data.extension.latest_version
+    );
+    for entry in data.archive.shared_objects() {
+        match Hardening::analyze(&entry.contents) {
+            Result::Ok(hardening) => println!("  {}: {hardening}", entry.path.display()),
+            Err(err) => println!("  {}: {err:#}", entry.path.display()),
+        }
+        match PreloadRequirement::analyze(&entry.contents) {
+            Result::Ok(requirement) if requirement.preload != Preload::None => {
+                println!("  {}: {}", entry.path.display(), requirement.yellow())
+            }
+            // Parse errors were already reported along with the hardening
+            _ => {}
+        }
+    }
+
+    let graph = DependencyGraph {
+        extension: &data.extension.name,
+        version: &data.extension.latest_version,
+        objects: &data.dependencies.objects,
+    };
+    print!("{}", graph.render_tree()?);
+
+    Ok(())
+}
+
+/// Analyze every extension, live from the registry or from a mirror, and report the sonames whose
+/// package is unknown by how many extensions they block
+async fn report_unknown_sonames(
+    UnknownSonames {
+        base_url,
+        mirror: mirror_dir,
+        pg_version,
+        mappings,
+        packages_index,
+        output,
+        commented,
+    }: UnknownSonames,
+) -> Result {
+    let mappings = mappings
+        .map(|path| SonameMappings::from_file(&path, false))
+        .transpose()?;
+    let index = if packages_index.is_empty() {
+        None
+    } else {
+        Some(PackagesIndex::from_files(&packages_index)?)
+    };
+
+    let mut fetched = Vec::new();
+    match (base_url, mirror_dir) {
+        (Some(base_url), None) => {
+            let client = Client::new(base_url);
+            let extensions = client.fetch_extensions().await?;
+            let mut handles = Vec::with_capacity(extensions.len());
+
+            for extension in extensions {
+                let name = extension.name.clone();
+                let work = Dependencies::fetch_from_archive(extension, client.clone(), pg_version);
+
+                handles.push((name, tokio::spawn(work)));
+            }
+            for (name, handle) in handles {
+                fetched.push((name, handle.await?));
+            }
+        }
+        (None, Some(mirror_dir)) => {
+            for (extension, path) in mirror::mirror_archives(&mirror_dir)? {
+                let name = extension.name.clone();
+                let data = utils::read_to_vec(&path).and_then(|tar_gz| {
+                    Dependencies::decompress_archive(extension, Vec::new(), &tar_gz)
+                });
+
+                fetched.push((name, data));
+            }
+        }
+        _ => anyhow::bail!("Either --base-url or --mirror must be given"),
+    }
+
+    let mut report = UnknownSonameReport::default();
+    for (name, data) in fetched {
+        match data {
+            Result::Ok(mut data) => report.add(&name, &mut data.dependencies, mappings.as_ref()),
+            Err(err) => eprintln!("Err: {name}: {err:#}"),
+        }
+    }
+
+    print!("{}", report.render(mappings.as_ref(), index.as_ref())?);
+    if let Some(output) = output {
+        fs_err::write(
+            &output,
+            report.render_mappings(mappings.as_ref(), index.as_ref(), commented),
+        )?;
+        println!("Wrote the unknown sonames to {}", output.display());
+    }
+
+    Ok(())
+}
+
+/// Answer which extension packages need a soname or package from the persisted dependency index,
+/// after rebuilding it if a registry or mirror is given
+async fn who_needs(
+    WhoNeeds {
+        name,
+        index: index_path,
+        base_url,
+        mirror: mirror_dir,
+        pg_version,
+        packages_index,
+    }: WhoNeeds,
+) -> Result {
+    let needs_index = match (base_url, mirror_dir) {
+        (None, None) => NeedsIndex::load(&index_path).with_context(|| {
+            format!(
+                "No dependency index at {}, build it with --base-url or --mirror",
+                index_path.display()
+            )
+        })?,
+        (base_url, mirror_dir) => {
+            let packages_index = if packages_index.is_empty() {
+                None
+            } else {
+                Some(PackagesIndex::from_files(&packages_index)?)
+            };
+
+            let fetched = match (base_url, mirror_dir) {
+                (Some(base_url), None) => fetch_every_version(base_url, pg_version).await?,
+                (None, Some(mirror_dir)) => mirror::mirror_versions(&mirror_dir)?
+                    .into_iter()
+                    .flat_map(|(name, versions)| {
+                        versions.into_iter().map(move |(version, path)| {
+                            let extension = Extension::unpublished(name.clone(), version);
+                            let label = format!("{} {}", extension.name, extension.latest_version);
+                            let data = utils::read_to_vec(&path).and_then(|tar_gz| {
+                                Dependencies::decompress_archive(extension, Vec::new(), &tar_gz)
+                            });
+
+                            (label, data)
+                        })
+                    })
+                    .collect(),
+                _ => anyhow::bail!("--base-url and --mirror can't be used together"),
+            };
+
+            let mut entries = Vec::with_capacity(fetched.len());
+            for (label, data) in fetched {
+                match data {
+                    Result::Ok(data) => {
+                        entries.push(IndexEntry::new(&data, pg_version, packages_index.as_ref()))
+                    }
+                    Err(err) => eprintln!("Err: {label}: {err:#}"),
+                }
+            }
+
+            let needs_index = NeedsIndex {
+                pg_version,
+                entries,
+            };
+            needs_index.save(&index_path)?;
+            eprintln!(
+                "Indexed {} package version(s) into {}",
+                needs_index.entries.len(),
+                index_path.display()
+            );
+
+            needs_index
+        }
+    };
+
+    print!("{}", needs_index.render(&name)?);
+
+    Ok(())
+}
+
+/// Download and analyze the archive of every published version of every extension, labelled by
+/// extension and version
+async fn fetch_every_version(
+    base_url: String,
+    pg_version: u16,
+) -> Result<Vec<(String, Result<FetchData>)>> {
+    let client = Client::new(base_url);
+    let extensions = client.fetch_extensions().await?;
+    let mut handles = Vec::with_capacity(extensions.len());
+
+    for extension in extensions {
+        let client = client.clone();
+
+        let work = async move {
+            let versions = client.version_history(&extension.name).await;
+
+            // Without a history, at least the latest version is indexed
+            if versions.is_empty() {
+                let label = format!("{} {}", extension.name, extension.latest_version);
+                let data =
+                    Dependencies::fetch_from_archive(extension, client.clone(), pg_version).await;
+
+                return vec![(label, data)];
+            }
+
+            let mut fetched = Vec::with_capacity(versions.len());
+            for version in versions {
+                let label = format!("{} {}", extension.name, version.version);
+                let data = client
+                    .fetch_version_archive(&extension.name, &version.version, pg_version)
+                    .await
+                    .and_then(|archive| {
+                        let extension = Extension {
+                            latest_version: version.version.clone(),
+                            ..extension.clone()
+                        };
+
+                        Dependencies::analyze_archive(extension, Vec::new(), archive)
+                    });
+                fetched.push((label, data));
+            }
+
+            fetched
         };
 
         handles.push(tokio::spawn(work));
     }
 
-    let mut failing_extensions = Vec::with_capacity(24);
-
+    let mut fetched = Vec::new();
     for handle in handles {
-        if let Err(failing_extension) = handle.await? {
-            failing_extensions.push(failing_extension);
-        }
+        fetched.extend(handle.await?);
     }
 
-    for failing_extension in failing_extensions {
-        eprintln!("Err: {failing_extension}");
+    Ok(fetched)
+}
+
+fn inspect(deb: &Path, json: bool) -> Result {
+    let deb_file = DebFile::read(deb)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&deb_file)?);
+    } else {
+        print!("{}", deb_file.describe()?);
+    }
+
+    Ok(())
+}
+
+fn run_lint(
+    Lint {
+        debs,
+        format,
+        fail_on,
+    }: Lint,
+) -> Result {
+    let findings = lint::lint_files(&debs)?;
+    print!("{}", lint::render(&findings, format)?);
+    if format != lint::LintFormat::Text {
+        println!();
+    }
+
+    let failing = findings
+        .iter()
+        .filter(|finding| finding.severity >= fail_on)
+        .count();
+    if failing > 0 {
+        anyhow::bail!("{failing} finding(s) at or above {fail_on}");
     }
 
     Ok(())
@@ -145,19 +609,38 @@ async fn package_all_extensions(base_url: String, export_dir: PathBuf) -> Result
 #[tokio::main]
 async fn main() -> Result {
     match cli::parse_args() {
-        Subcommands::ShowSharedObjects(_) => Ok(()),
-        Subcommands::PackageAll(PackageAll {
-            base_url,
-            export_dir,
-        }) => package_all_extensions(base_url, export_dir).await,
-        Subcommands::PackageOne(PackageOne {
+        Subcommands::ShowSharedObjects(ShowSharedObjects {
             base_url,
-            trunk_project_name,
-            export_dir,
-            file,
-        }) => {
+            pg_version,
+            format,
+        }) => show_shared_objects(base_url, pg_version, format).await,
+        Subcommands::Inspect(Inspect { deb, json }) => inspect(&deb, json),
+        Subcommands::Lint(args) => run_lint(args),
+        Subcommands::Report(Report {
+            report: Reports::UnknownSonames(args),
+        }) => report_unknown_sonames(args).await,
+        Subcommands::WhoNeeds(args) => who_needs(args).await,
+        Subcommands::PackageAll(args) => {
+            let all_options = BuildOptions::from_args(args.packaging_args())?;
+            let PackageAll {
+                base_url,
+                export_dir,
+                ..
+            } = args;
+
+            package_all_extensions(base_url, export_dir, all_options).await
+        }
+        Subcommands::PackageOne(args) => {
+            let all_options = BuildOptions::from_args(args.packaging_args())?;
+            let PackageOne {
+                base_url,
+                trunk_project_name,
+                export_dir,
+                file,
+                ..
+            } = args;
             let export_dir = std::fs::canonicalize(export_dir)?;
-            package_extension(base_url, trunk_project_name, export_dir, file).await
+            package_extension(base_url, trunk_project_name, export_dir, file, all_options).await
         }
     }
 }
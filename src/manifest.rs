@@ -0,0 +1,124 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Not,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::Extension,
+    unarchiver::{Archive, EntryKind},
+    Result,
+};
+
+/// What a file listed in the manifest is
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(tag = "type")]
+pub enum PackagedFile {
+    ControlFile,
+    SqlFile,
+    SharedObject,
+    Bitcode,
+    Extra,
+    #[serde(other)]
+    Unknown,
+}
+
+/// The `manifest.json` file Trunk writes at the root of every archive it builds
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct TrunkManifest {
+    pub extension_name: String,
+    pub extension_version: String,
+    #[serde(default)]
+    pub manifest_version: Option<u32>,
+    /// The PostgreSQL major version the archive was built against
+    #[serde(default)]
+    pub pg_version: Option<u16>,
+    /// Machine architecture, as reported by `uname -m`
+    #[serde(default)]
+    pub architecture: Option<String>,
+    /// Operating system, e.g. `linux`
+    #[serde(default)]
+    pub sys: Option<String>,
+    /// System packages required at runtime, keyed by package manager, e.g. `apt`
+    #[serde(default)]
+    pub dependencies: Option<HashMap<String, Vec<String>>>,
+    #[serde(default)]
+    pub files: Option<BTreeMap<PathBuf, PackagedFile>>,
+}
+
+impl TrunkManifest {
+    pub const FILE_NAME: &'static str = "manifest.json";
+
+    /// Parse the manifest of the given archive, if it has one
+    pub fn from_archive(archive: &Archive) -> Result<Option<Self>> {
+        let maybe_entry = archive.all_entries().iter().find(|entry| {
+            entry.kind == EntryKind::Regular
+                && entry.path.strip_prefix(".").unwrap_or(&entry.path) == Path::new(Self::FILE_NAME)
+        });
+
+        let Some(entry) = maybe_entry else {
+            return Ok(None);
+        };
+
+        let manifest = serde_json::from_slice(&entry.contents)
+            .with_context(|| format!("Failed to parse {}", Self::FILE_NAME))?;
+
+        Ok(Some(manifest))
+    }
+
+    /// The Debian packages the extension declared it needs, if any
+    pub fn apt_dependencies(&self) -> Option<&[String]> {
+        self.dependencies.as_ref()?.get("apt").map(Vec::as_slice)
+    }
+
+    /// The Debian name of the architecture the archive was built for
+    pub fn debian_architecture(&self) -> Option<&str> {
+        let architecture = match self.architecture.as_deref()? {
+            "x86_64" | "amd64" => "amd64",
+            "aarch64" | "arm64" => "arm64",
+            other => other,
+        };
+
+        Some(architecture)
+    }
+
+    /// Warn about every way in which the manifest disagrees with the registry or the archive itself
+    pub fn cross_check(&self, extension: &Extension, archive: &Archive) {
+        let name = &extension.name;
+
+        if self.extension_name != extension.name {
+            eprintln!(
+                "{name}: manifest names the extension {}, while the registry calls it {name}",
+                self.extension_name
+            );
+        }
+
+        if self.extension_version != extension.latest_version {
+            eprintln!(
+                "{name}: manifest has version {}, while the registry's latest is {}",
+                self.extension_version, extension.latest_version
+            );
+        }
+
+        if let Some(sys) = self.sys.as_deref().filter(|sys| *sys != "linux") {
+            eprintln!("{name}: archive was built for {sys}, not Linux");
+        }
+
+        for listed_file in self.files.iter().flat_map(BTreeMap::keys) {
+            let is_in_archive = archive
+                .all_entries()
+                .iter()
+                .any(|entry| entry.path.strip_prefix(".").unwrap_or(&entry.path) == listed_file);
+
+            if is_in_archive.not() {
+                eprintln!(
+                    "{name}: manifest lists {}, which is missing from the archive",
+                    listed_file.display()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+    io::Write,
+    ops::Not,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use fs_err::OpenOptions;
+
+use crate::{
+    dependencies::{self, DependencySupplier},
+    packages_index::PackagesIndex,
+    utils, Result,
+};
+
+/// How many packages are suggested for an unknown soname
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Packages supplying sonames the built-in table lacks, from a file of `soname package` lines
+/// such as `libproj.so.25 libproj25`, the format of `libraries-found`
+#[derive(Debug)]
+pub struct SonameMappings {
+    path: PathBuf,
+    suppliers: HashMap<String, String>,
+    /// Every soname the file mentions, including commented-out stubs, so that none is added twice
+    mentioned: Mutex<BTreeSet<String>>,
+}
+
+impl SonameMappings {
+    /// Load the mappings of `path`, which may only be missing when it's about to be written to
+    pub fn from_file(path: &Path, may_be_missing: bool) -> Result<Self> {
+        let contents = if may_be_missing && path.exists().not() {
+            Vec::new()
+        } else {
+            utils::read_to_vec(path)?
+        };
+        let contents = String::from_utf8(contents)
+            .with_context(|| format!("{} is not valid UTF-8", path.display()))?;
+
+        let mut suppliers = HashMap::new();
+        let mut mentioned = BTreeSet::new();
+        for (idx, line) in contents.lines().enumerate() {
+            let (line, is_comment) = match line.trim().strip_prefix('#') {
+                Some(line) => (line.trim(), true),
+                None => (line.trim(), false),
+            };
+            if line.is_empty() {
+                continue;
+            }
+
+            match line.split_once(char::is_whitespace) {
+                Some((soname, package)) => {
+                    mentioned.insert(soname.to_owned());
+                    if is_comment.not() {
+                        suppliers.insert(soname.to_owned(), package.trim().to_owned());
+                    }
+                }
+                None if is_comment => {}
+                None => anyhow::bail!(
+                    "{}:{}: expected a soname and its package, found `{line}`",
+                    path.display(),
+                    idx + 1
+                ),
+            }
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            suppliers,
+            mentioned: Mutex::new(mentioned),
+        })
+    }
+
+    /// The packages supplying `soname`, if the file maps it
+    pub fn supplier(&self, soname: &str) -> Option<DependencySupplier> {
+        self.suppliers
+            .get(soname)
+            .map(|relation| DependencySupplier::parse(relation))
+    }
+
+    /// Append a commented-out line for each soname the file doesn't mention yet, naming the
+    /// best suggestion, so that it only has to be checked and uncommented
+    pub fn append_stubs(&self, extension_name: &str, unknown: &[UnknownSoname]) -> Result {
+        let mut mentioned = self
+            .mentioned
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Lost the lock on {}", self.path.display()))?;
+
+        // Whoever needs the sonames goes on a line of its own, so that stubs can simply be uncommented
+        let mut stubs = format!("# needed by {extension_name}\n");
+        let header = stubs.len();
+        for unknown in unknown {
+            if mentioned.insert(unknown.soname.clone()).not() {
+                continue;
+            }
+
+            stubs.push_str(&unknown.stub());
+        }
+        if stubs.len() == header {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(stubs.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// A package which may supply an unknown soname, and why we think so
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Suggestion {
+    pub package: String,
+    pub reasons: Vec<String>,
+    score: u8,
+}
+
+impl Display for Suggestion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.reasons.is_empty() {
+            f.write_str(&self.package)
+        } else {
+            write!(f, "{} ({})", self.package, self.reasons.join(", "))
+        }
+    }
+}
+
+/// A soname whose package is unknown, along with the packages most likely to supply it
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UnknownSoname {
+    pub soname: String,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl UnknownSoname {
+    /// Suggest packages from the soname's stem: by Debian's library package naming, by the
+    /// mapped sonames sharing that stem, and by the packages of `index`
+    pub fn new(
+        soname: &str,
+        mappings: Option<&SonameMappings>,
+        index: Option<&PackagesIndex>,
+    ) -> Self {
+        let (stem, version) = split_soname(soname);
+        // Scores: 1 for the naming convention, 2 for a mapped sibling, 4 for being in the index
+        let mut candidates: BTreeMap<String, (u8, Vec<String>)> = BTreeMap::new();
+        let mut add = |package: String, score: u8, reason: Option<String>| {
+            let (total, reasons) = candidates.entry(package).or_default();
+            *total |= score;
+            reasons.extend(reason);
+        };
+
+        // e.g. `libproj25` for `libproj.so.25` and `liblz4-1` for `liblz4.so.1`
+        let name = stem.to_lowercase().replace('_', "-");
+        let conventional = match version {
+            "" => name.clone(),
+            version if name.ends_with(|ch: char| ch.is_ascii_digit()) => {
+                format!("{name}-{version}")
+            }
+            version => format!("{name}{version}"),
+        };
+        add(conventional, 1, None);
+
+        let mut mapped: Vec<(&str, &str)> = dependencies::known_suppliers().collect();
+        mapped.extend(
+            mappings
+                .into_iter()
+                .flat_map(|mappings| &mappings.suppliers)
+                .map(|(soname, relation)| (soname.as_str(), relation.as_str())),
+        );
+        let siblings = mapped
+            .into_iter()
+            .filter(|(sibling, _)| *sibling != soname && split_soname(sibling).0 == stem);
+        for (sibling, relation) in siblings {
+            let sibling_version = split_soname(sibling).1;
+
+            for package in DependencySupplier::parse(relation).packages() {
+                // e.g. `libproj22` becomes `libproj25`
+                let package_version = package
+                    .rfind(sibling_version)
+                    .filter(|_| sibling_version.is_empty().not() && version.is_empty().not());
+                let candidate = match package_version {
+                    Some(at) => format!(
+                        "{}{version}{}",
+                        &package[..at],
+                        &package[at + sibling_version.len()..]
+                    ),
+                    None => package.to_owned(),
+                };
+
+                add(
+                    candidate,
+                    2,
+                    Some(format!("{sibling} → {package} is mapped")),
+                );
+            }
+        }
+
+        if let Some(index) = index {
+            // e.g. `libproj25t64` for `libproj.so.25`, but neither `libproj-dev` nor `libproj255`
+            let major = version.split('.').next().unwrap_or(version);
+            let listed: Vec<String> = index
+                .package_names()
+                .filter(|package| {
+                    let Some(rest) = package.strip_prefix(&name) else {
+                        return false;
+                    };
+                    let rest = rest.trim_start_matches('-');
+
+                    match rest.strip_prefix(major) {
+                        Some(rest) => {
+                            rest.starts_with(|ch: char| ch.is_ascii_alphanumeric())
+                                .not()
+                                || rest == "t64"
+                        }
+                        None => false,
+                    }
+                })
+                .map(str::to_owned)
+                .collect();
+            for package in listed {
+                add(package, 0, None);
+            }
+
+            for (package, (score, reasons)) in &mut candidates {
+                if index.contains(package) {
+                    *score |= 4;
+                    reasons.push("in the packages index".into());
+                }
+            }
+            // Only what the index has is worth suggesting once it's known
+            if candidates.values().any(|(score, _)| score & 4 != 0) {
+                candidates.retain(|_, (score, _)| *score & 4 != 0);
+            }
+        }
+
+        let mut suggestions: Vec<_> = candidates
+            .into_iter()
+            .filter(|(_, (score, _))| *score > 0)
+            .map(|(package, (score, reasons))| Suggestion {
+                package,
+                reasons,
+                score,
+            })
+            .collect();
+        suggestions.sort_by_key(|suggestion| Reverse(suggestion.score));
+        suggestions.truncate(MAX_SUGGESTIONS);
+
+        Self {
+            soname: soname.to_owned(),
+            suggestions,
+        }
+    }
+}
+
+impl UnknownSoname {
+    /// A `soname package` line naming the best suggestion, or `?` without any
+    pub fn mapping(&self) -> String {
+        let package = self
+            .suggestions
+            .first()
+            .map_or("?", |suggestion| suggestion.package.as_str());
+
+        format!("{} {package}\n", self.soname)
+    }
+
+    /// The mapping line, commented out until someone checks it
+    pub fn stub(&self) -> String {
+        format!("# {}", self.mapping())
+    }
+}
+
+impl Display for UnknownSoname {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.suggestions.is_empty() {
+            return write!(f, "{}: no package suggestion", self.soname);
+        }
+
+        let suggestions: Vec<_> = self.suggestions.iter().map(Suggestion::to_string).collect();
+        write!(
+            f,
+            "{}: did you mean {}?",
+            self.soname,
+            suggestions.join(" or ")
+        )
+    }
+}
+
+/// Split a soname such as `libproj.so.25` into its stem and version, `libproj` and `25`
+fn split_soname(soname: &str) -> (&str, &str) {
+    match soname.split_once(".so") {
+        Some((stem, version)) => (stem, version.trim_start_matches('.')),
+        None => (soname, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packages_index::PackagesIndex;
+
+    use super::UnknownSoname;
+
+    fn packages(unknown: &UnknownSoname) -> Vec<&str> {
+        unknown
+            .suggestions
+            .iter()
+            .map(|suggestion| suggestion.package.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn naming_convention() {
+        let unknown = UnknownSoname::new("liblz4.so.1", None, None);
+        assert_eq!(packages(&unknown), ["liblz4-1"]);
+
+        let unknown = UnknownSoname::new("libFoo_bar.so", None, None);
+        assert_eq!(packages(&unknown), ["libfoo-bar"]);
+    }
+
+    #[test]
+    fn mapped_sibling() {
+        // The built-in table maps `libproj.so.22` to `libproj22`
+        let unknown = UnknownSoname::new("libproj.so.25", None, None);
+
+        assert_eq!(packages(&unknown), ["libproj25"]);
+        assert_eq!(
+            unknown.suggestions[0].reasons,
+            ["libproj.so.22 → libproj22 is mapped"]
+        );
+        assert_eq!(unknown.stub(), "# libproj.so.25 libproj25\n");
+    }
+
+    #[test]
+    fn packages_index() {
+        let path = std::env::temp_dir().join(format!("Packages-{}", std::process::id()));
+        fs_err::write(
+            &path,
+            "Package: libproj25t64\nVersion: 9.4.0-1\n\n\
+             Package: libproj-dev\nVersion: 9.4.0-1\n\n\
+             Package: libproj255\nVersion: 1.0\n",
+        )
+        .unwrap();
+        let index = PackagesIndex::from_files(std::slice::from_ref(&path)).unwrap();
+        fs_err::remove_file(&path).unwrap();
+
+        // Once the index is known, only what it has is suggested
+        let unknown = UnknownSoname::new("libproj.so.25", None, Some(&index));
+        assert_eq!(packages(&unknown), ["libproj25t64"]);
+
+        let unknown = UnknownSoname::new("libbar.so.2", None, Some(&index));
+        assert_eq!(packages(&unknown), ["libbar2"]);
+        assert_eq!(unknown.stub(), "# libbar.so.2 libbar2\n");
+    }
+}
@@ -0,0 +1,61 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{client::Extension, utils, Result};
+
+/// Every archive in `mirror`, a directory of Trunk archives named like `pg_cron-1.6.2.tar.gz`,
+/// by extension, oldest version first
+pub fn mirror_versions(mirror: &Path) -> Result<BTreeMap<String, Vec<(String, PathBuf)>>> {
+    let mut versions: BTreeMap<String, Vec<(String, PathBuf)>> = BTreeMap::new();
+
+    for entry in fs_err::read_dir(mirror)? {
+        let path = entry?.path();
+        let Some(stem) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".tar.gz"))
+        else {
+            continue;
+        };
+        // The version starts after the last `-` followed by a digit
+        let split = stem
+            .rmatch_indices('-')
+            .find(|(at, _)| stem[at + 1..].starts_with(|ch: char| ch.is_ascii_digit()))
+            .map(|(at, _)| at);
+        let Some(split) = split else {
+            eprintln!(
+                "{}: no version in the file name, skipping it",
+                path.display()
+            );
+            continue;
+        };
+        let (name, version) = (&stem[..split], &stem[split + 1..]);
+
+        versions
+            .entry(name.to_owned())
+            .or_default()
+            .push((version.to_owned(), path.clone()));
+    }
+
+    for archives in versions.values_mut() {
+        archives.sort_by(|(left, _), (right, _)| utils::compare_versions(left, right));
+    }
+
+    Ok(versions)
+}
+
+/// The newest archive of every extension in `mirror`
+pub fn mirror_archives(mirror: &Path) -> Result<Vec<(Extension, PathBuf)>> {
+    let archives = mirror_versions(mirror)?
+        .into_iter()
+        .filter_map(|(name, mut versions)| {
+            let (latest_version, path) = versions.pop()?;
+
+            Some((Extension::unpublished(name, latest_version), path))
+        })
+        .collect();
+
+    Ok(archives)
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt::Write,
+    ops::Not,
+    path::Path,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    deb_packager::DebPackager, dependencies::FetchData, packages_index::PackagesIndex, utils,
+    Result,
+};
+
+/// A soname or package an extension needs, and how
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Need {
+    /// A soname such as `libcurl.so.4`, or a package such as `libcurl4`
+    pub name: String,
+    /// What it's needed through, e.g. a library the archive bundles or a package depending on
+    /// it. Empty when the extension's own shared objects need it.
+    pub via: Vec<String>,
+    /// Only loaded with `dlopen`, so the package merely recommends or suggests it
+    pub dlopen: bool,
+}
+
+/// Everything one version of an extension needs
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IndexEntry {
+    pub extension: String,
+    pub package: String,
+    pub version: String,
+    pub needs: Vec<Need>,
+}
+
+impl IndexEntry {
+    /// Walk the shared objects of an archive from those nothing in it links against, so that
+    /// what the libraries it bundles need is told apart. With an `index`, the packages those
+    /// depend on are followed as well.
+    pub fn new(data: &FetchData, pg_major: u16, index: Option<&PackagesIndex>) -> Self {
+        let objects = &data.dependencies.objects;
+        let file_name = |path: &str| path.rsplit('/').next().unwrap_or(path).to_owned();
+        let bundled: BTreeMap<String, &str> = objects
+            .keys()
+            .map(|path| (file_name(path), path.as_str()))
+            .collect();
+        let linked: BTreeSet<&str> = objects
+            .values()
+            .flat_map(|object| &object.needed)
+            .map(|library| library.soname.as_str())
+            .collect();
+
+        let mut roots: Vec<&str> = objects
+            .keys()
+            .filter(|path| linked.contains(file_name(path).as_str()).not())
+            .map(String::as_str)
+            .collect();
+        // Libraries which all link against each other are all loaded by the server
+        if roots.is_empty() {
+            roots = objects.keys().map(String::as_str).collect();
+        }
+
+        let mut needs: BTreeMap<String, (Vec<String>, bool)> = BTreeMap::new();
+        let mut need = |name: &str, via: &[String], dlopen: bool| {
+            Self::insert_need(&mut needs, name, via, dlopen);
+        };
+
+        // What the manifest declares ends up in `Depends`, so it's always needed directly
+        for relation in data.dependencies.declared.iter().flatten() {
+            for alternative in relation.split('|') {
+                let package = alternative
+                    .split(|ch: char| ch.is_whitespace() || ch == '(')
+                    .find(|name| name.is_empty().not());
+                if let Some(package) = package {
+                    need(package, &[], false);
+                }
+            }
+        }
+
+        let mut visited: BTreeSet<&str> = roots.iter().copied().collect();
+        let mut queue: VecDeque<(&str, Vec<String>)> =
+            roots.into_iter().map(|path| (path, Vec::new())).collect();
+        while let Some((path, via)) = queue.pop_front() {
+            let Some(object) = objects.get(path) else {
+                continue;
+            };
+
+            for library in &object.needed {
+                if let Some(bundled) = bundled.get(&library.soname) {
+                    if visited.insert(bundled) {
+                        let mut via = via.clone();
+                        via.push(library.soname.clone());
+                        queue.push_back((bundled, via));
+                    }
+                    continue;
+                }
+
+                need(&library.soname, &via, false);
+                for package in library.supplier.packages() {
+                    need(package, &via, false);
+                }
+            }
+            for library in &object.dlopened {
+                need(&library.soname, &via, true);
+                for package in library.supplier.packages() {
+                    need(package, &via, true);
+                }
+            }
+        }
+
+        // The packages the needed packages depend on, breadth first
+        if let Some(index) = index {
+            let mut queue: VecDeque<(String, Vec<String>, bool)> = needs
+                .iter()
+                .map(|(name, (via, dlopen))| (name.clone(), via.clone(), *dlopen))
+                .collect();
+
+            while let Some((package, via, dlopen)) = queue.pop_front() {
+                for dependency in index.depends(&package) {
+                    let mut via = via.clone();
+                    via.push(package.clone());
+
+                    if Self::insert_need(&mut needs, dependency, &via, dlopen) {
+                        queue.push_back((dependency.to_owned(), via, dlopen));
+                    }
+                }
+            }
+        }
+
+        Self {
+            extension: data.extension.name.clone(),
+            package: DebPackager::package_name(&data.extension.name, pg_major),
+            version: data.extension.latest_version.clone(),
+            needs: needs
+                .into_iter()
+                .map(|(name, (via, dlopen))| Need { name, via, dlopen })
+                .collect(),
+        }
+    }
+
+    /// Record that `name` is needed through `via`. Breadth first, the first way to need
+    /// something is also the shortest, so it's only replaced when linking makes a library
+    /// loaded with `dlopen` mandatory. Returns whether anything changed.
+    fn insert_need(
+        needs: &mut BTreeMap<String, (Vec<String>, bool)>,
+        name: &str,
+        via: &[String],
+        dlopen: bool,
+    ) -> bool {
+        match needs.get(name) {
+            Some((_, was_dlopen)) if *was_dlopen && dlopen.not() => {}
+            Some(_) => return false,
+            None => {}
+        }
+
+        needs.insert(name.to_owned(), (via.to_vec(), dlopen));
+        true
+    }
+}
+
+/// What every version of every extension needs, persisted as JSON so that queries don't have to
+/// download and analyze any archive
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NeedsIndex {
+    pub pg_version: u16,
+    pub entries: Vec<IndexEntry>,
+}
+
+impl NeedsIndex {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = utils::read_to_vec(path)?;
+
+        serde_json::from_slice(&contents)
+            .with_context(|| format!("Failed to parse the dependency index {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result {
+        fs_err::write(path, serde_json::to_vec_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    /// The package versions needing `name`, a soname or a package, along with how they need it
+    pub fn who_needs<'a>(&'a self, name: &str) -> Vec<(&'a IndexEntry, &'a Need)> {
+        let mut found: Vec<_> = self
+            .entries
+            .iter()
+            .flat_map(|entry| entry.needs.iter().map(move |need| (entry, need)))
+            .filter(|(_, need)| need.name == name)
+            .collect();
+        found.sort_by(|(left, _), (right, _)| {
+            left.package
+                .cmp(&right.package)
+                .then_with(|| utils::compare_versions(&left.version, &right.version))
+        });
+
+        found
+    }
+
+    pub fn render(&self, name: &str) -> Result<String> {
+        let found = self.who_needs(name);
+        let mut report = String::with_capacity(512);
+
+        if found.is_empty() {
+            writeln!(
+                report,
+                "Nothing in the index of {} package version(s) for PostgreSQL {} needs {name}",
+                self.entries.len(),
+                self.pg_version
+            )?;
+            return Ok(report);
+        }
+
+        let packages: BTreeSet<&str> = found
+            .iter()
+            .map(|(entry, _)| entry.package.as_str())
+            .collect();
+        writeln!(
+            report,
+            "{name} is needed by {} version(s) of {} package(s):",
+            found.len(),
+            packages.len()
+        )?;
+        for (entry, need) in found {
+            let link = if need.via.is_empty() {
+                "direct".to_owned()
+            } else {
+                format!("transitive, through {}", need.via.join(" → "))
+            };
+            let dlopen = if need.dlopen { ", with dlopen" } else { "" };
+
+            writeln!(
+                report,
+                "  {} {}: {link}{dlopen}",
+                entry.package, entry.version
+            )?;
+        }
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    ops::Not,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{compression::Compression, utils, Result};
+
+/// The packages of a distribution release, as listed by its `Packages` indexes
+#[derive(Default, Debug)]
+pub struct PackagesIndex {
+    /// Every version of each package
+    packages: HashMap<String, Vec<String>>,
+    /// The packages providing each virtual package, with the version they provide, if any
+    provides: HashMap<String, Vec<(String, Option<String>)>>,
+    /// The packages each package depends on, any of whose alternatives may do
+    depends: HashMap<String, Vec<String>>,
+}
+
+impl PackagesIndex {
+    /// Load `Packages`, `Packages.gz` or `Packages.xz` files
+    pub fn from_files(paths: &[PathBuf]) -> Result<Self> {
+        let mut index = Self::default();
+
+        for path in paths {
+            let bytes = utils::read_to_vec(path)?;
+            let compression = match path.extension().and_then(|extension| extension.to_str()) {
+                Some("gz") => Compression::Gzip,
+                Some("xz") => Compression::Xz,
+                _ => Compression::None,
+            };
+            let bytes = compression
+                .decompress(&bytes)
+                .with_context(|| format!("Failed to decompress {}", path.display()))?;
+            let text = String::from_utf8(bytes)
+                .with_context(|| format!("{} is not valid UTF-8", path.display()))?;
+
+            index.add_stanzas(&text, path);
+        }
+
+        Ok(index)
+    }
+
+    fn add_stanzas(&mut self, text: &str, path: &Path) {
+        for stanza in text.split("\n\n") {
+            let mut package = None;
+            let mut version = None;
+            let mut provides = None;
+            let mut depends = Vec::new();
+
+            // Continuation lines start with whitespace and belong to fields we don't read
+            for line in stanza.lines() {
+                match line.split_once(':') {
+                    Some(("Package", value)) => package = Some(value.trim()),
+                    Some(("Version", value)) => version = Some(value.trim()),
+                    Some(("Provides", value)) => provides = Some(value.trim()),
+                    Some(("Depends" | "Pre-Depends", value)) => depends.push(value.trim()),
+                    _ => {}
+                }
+            }
+
+            let (Some(package), Some(version)) = (package, version) else {
+                if stanza.trim().is_empty().not() {
+                    eprintln!(
+                        "{}: skipping a stanza without Package or Version",
+                        path.display()
+                    );
+                }
+                continue;
+            };
+
+            for provided in provides.into_iter().flat_map(|value| value.split(',')) {
+                let (name, constraint) = parse_alternative(provided);
+                let version = constraint.map(|(_, version)| version.to_owned());
+
+                self.provides
+                    .entry(name.to_owned())
+                    .or_default()
+                    .push((package.to_owned(), version));
+            }
+
+            let depended_on = depends
+                .into_iter()
+                .flat_map(|value| value.split([',', '|']))
+                .map(|alternative| parse_alternative(alternative).0.to_owned())
+                .filter(|name| name.is_empty().not());
+            self.depends
+                .entry(package.to_owned())
+                .or_default()
+                .extend(depended_on);
+
+            self.packages
+                .entry(package.to_owned())
+                .or_default()
+                .push(version.to_owned());
+        }
+    }
+
+    /// Whether `name` is a package or provided by one
+    pub fn contains(&self, name: &str) -> bool {
+        self.packages.contains_key(name) || self.provides.contains_key(name)
+    }
+
+    pub fn package_names(&self) -> impl Iterator<Item = &str> {
+        self.packages.keys().map(String::as_str)
+    }
+
+    /// The packages `package` depends on, in any of the versions the index has
+    pub fn depends(&self, package: &str) -> impl Iterator<Item = &str> {
+        self.depends
+            .get(package)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+    }
+
+    /// Check that a relation such as `libssl3 (>= 3.0) | libssl3t64` can be satisfied,
+    /// explaining why none of its alternatives can otherwise
+    pub fn check(&self, relation: &str) -> std::result::Result<(), String> {
+        let mut reasons = Vec::new();
+
+        for alternative in relation.split('|') {
+            match self.check_alternative(alternative) {
+                Ok(()) => return Ok(()),
+                Err(reason) => reasons.push(reason),
+            }
+        }
+
+        Err(reasons.join("; "))
+    }
+
+    fn check_alternative(&self, alternative: &str) -> std::result::Result<(), String> {
+        let (name, constraint) = parse_alternative(alternative);
+        let versions = self.packages.get(name);
+        let providers = self.provides.get(name);
+
+        if versions.is_none() && providers.is_none() {
+            return Err(format!("{name} is neither a package nor provided by one"));
+        }
+
+        let Some((operator, wanted)) = constraint else {
+            return Ok(());
+        };
+
+        let satisfies = |version: &str| -> std::result::Result<bool, String> {
+            let ordering = utils::compare_debian_versions(version, wanted);
+
+            match operator {
+                ">=" | ">" => Ok(ordering.is_ge()),
+                "<=" | "<" => Ok(ordering.is_le()),
+                ">>" => Ok(ordering.is_gt()),
+                "<<" => Ok(ordering.is_lt()),
+                "=" => Ok(ordering.is_eq()),
+                other => Err(format!("{name} has an unknown version operator `{other}`")),
+            }
+        };
+
+        for version in versions.into_iter().flatten() {
+            if satisfies(version)? {
+                return Ok(());
+            }
+        }
+        // Only versioned `Provides` can satisfy versioned dependencies
+        let provided_versions = providers
+            .into_iter()
+            .flatten()
+            .filter_map(|(_, version)| version.as_deref());
+        for version in provided_versions {
+            if satisfies(version)? {
+                return Ok(());
+            }
+        }
+
+        let mut available: Vec<String> = versions.into_iter().flatten().cloned().collect();
+        available.extend(providers.into_iter().flatten().map(
+            |(provider, version)| match version {
+                Some(version) => format!("{version} through {provider}"),
+                None => format!("unversioned through {provider}"),
+            },
+        ));
+
+        Err(format!(
+            "{name} ({operator} {wanted}) is not available, the index has {}",
+            available.join(", ")
+        ))
+    }
+}
+
+/// Split an alternative such as `libc6:any (>= 2.34)` into its package name and constraint
+fn parse_alternative(alternative: &str) -> (&str, Option<(&str, &str)>) {
+    let (name, constraint) = match alternative.split_once('(') {
+        Some((name, constraint)) => (name, Some(constraint.trim().trim_end_matches(')'))),
+        None => (alternative, None),
+    };
+    // The architecture qualifier doesn't matter for a single architecture's index
+    let name = name.trim();
+    let name = name.split_once(':').map_or(name, |(name, _)| name);
+
+    let constraint = constraint.map(|constraint| {
+        let split = constraint
+            .find(|ch: char| "<>=".contains(ch).not())
+            .unwrap_or(constraint.len());
+        let (operator, version) = constraint.split_at(split);
+
+        (operator.trim(), version.trim())
+    });
+
+    (name, constraint)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::PackagesIndex;
+
+    const PACKAGES: &str = "\
+Package: libssl3t64
+Version: 3.0.13-0ubuntu3
+Provides: libssl3 (= 3.0.13-0ubuntu3)
+Depends: libc6 (>= 2.34)
+
+Package: libc6
+Version: 2.39-0ubuntu8
+
+Package: libproj25
+Version: 9.4.0-1build2
+Provides: libproj
+
+Package: libcurl4t64
+Version: 1:8.5.0-2ubuntu10
+";
+
+    fn index() -> PackagesIndex {
+        let mut index = PackagesIndex::default();
+        index.add_stanzas(PACKAGES, Path::new("Packages"));
+
+        index
+    }
+
+    #[test]
+    fn unversioned() {
+        let index = index();
+
+        assert!(index.check("libc6").is_ok());
+        assert!(index.check("libc6:any").is_ok());
+        assert!(index.check("libproj").is_ok());
+        assert!(index.check("libfoo").is_err());
+        assert!(index.check("libfoo | libc6").is_ok());
+    }
+
+    #[test]
+    fn versions() {
+        let index = index();
+
+        assert!(index.check("libc6 (>= 2.34)").is_ok());
+        assert!(index.check("libc6 (>= 2.40)").is_err());
+        assert!(index.check("libc6 (<< 2.39)").is_err());
+        assert!(index.check("libc6 (>= 2.39~)").is_ok());
+        assert!(index.check("libc6 (= 2.39-0ubuntu8)").is_ok());
+        assert!(index.check("libc6 (>= 2.40) | libproj25").is_ok());
+    }
+
+    #[test]
+    fn epochs() {
+        let index = index();
+
+        assert!(index.check("libcurl4t64 (>= 8.6)").is_ok());
+        assert!(index.check("libcurl4t64 (>= 1:8.6)").is_err());
+        assert!(index.check("libcurl4t64 (>= 1:8.5.0)").is_ok());
+    }
+
+    #[test]
+    fn versioned_provides() {
+        let index = index();
+
+        assert!(index.check("libssl3 (>= 3.0)").is_ok());
+        assert!(index.check("libssl3 (>= 3.1)").is_err());
+        // Unversioned `Provides` can't satisfy a versioned dependency
+        let reason = index.check("libproj (>= 9)").unwrap_err();
+        assert!(reason.contains("unversioned through libproj25"), "{reason}");
+    }
+}
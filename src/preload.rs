@@ -0,0 +1,279 @@
+use std::{collections::BTreeSet, fmt::Display};
+
+use goblin::elf::Elf;
+
+use crate::{unarchiver::Archive, Result};
+
+/// Symbols that only work in a library loaded through `shared_preload_libraries`
+const SHARED_PRELOAD_SYMBOLS: [&str; 6] = [
+    "shmem_request_hook",
+    "shmem_startup_hook",
+    "RequestAddinShmemSpace",
+    "RequestNamedLWLockTranche",
+    "RegisterBackgroundWorker",
+    "process_shared_preload_libraries_in_progress",
+];
+
+/// Hooks which only take effect in the sessions that loaded the library
+const SESSION_HOOK_SYMBOLS: [&str; 17] = [
+    "ProcessUtility_hook",
+    "ExecutorStart_hook",
+    "ExecutorRun_hook",
+    "ExecutorFinish_hook",
+    "ExecutorEnd_hook",
+    "ExecutorCheckPerms_hook",
+    "planner_hook",
+    "post_parse_analyze_hook",
+    "ClientAuthentication_hook",
+    "emit_log_hook",
+    "object_access_hook",
+    "check_password_hook",
+    "needs_fmgr_hook",
+    "fmgr_hook",
+    "join_search_hook",
+    "set_rel_pathlist_hook",
+    "create_upper_paths_hook",
+];
+
+/// How a library has to be loaded before it works
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub enum Preload {
+    /// Loaded on demand, e.g. by `CREATE EXTENSION`
+    #[default]
+    None,
+    /// Installs hooks, so must be loaded into every session, e.g. by `session_preload_libraries`
+    Session,
+    /// Must be in `shared_preload_libraries`, which takes a server restart
+    Restart,
+}
+
+/// What a library needs to be preloaded for, and the symbols that gave it away
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct PreloadRequirement {
+    pub preload: Preload,
+    pub evidence: BTreeSet<String>,
+}
+
+impl PreloadRequirement {
+    /// Look for the server symbols a shared object imports
+    pub fn analyze(bytes: &[u8]) -> Result<Self> {
+        let elf = Elf::parse(bytes)?;
+        let mut requirement = Self::default();
+
+        let imports = elf
+            .dynsyms
+            .iter()
+            .filter(|symbol| symbol.is_import())
+            .filter_map(|symbol| elf.dynstrtab.get_at(symbol.st_name));
+
+        for name in imports {
+            let preload = if SHARED_PRELOAD_SYMBOLS.contains(&name) {
+                Preload::Restart
+            } else if SESSION_HOOK_SYMBOLS.contains(&name) {
+                Preload::Session
+            } else {
+                continue;
+            };
+
+            requirement.preload = requirement.preload.max(preload);
+            requirement.evidence.insert(name.to_owned());
+        }
+
+        Ok(requirement)
+    }
+}
+
+impl Display for PreloadRequirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let requirement = match self.preload {
+            Preload::None => return f.write_str("loaded on demand"),
+            Preload::Session => "must be loaded into every session",
+            Preload::Restart => "needs shared_preload_libraries and a restart",
+        };
+        let evidence: Vec<_> = self.evidence.iter().map(String::as_str).collect();
+
+        write!(f, "{requirement} ({})", evidence.join(", "))
+    }
+}
+
+/// The preload requirements of all the shared objects of an extension
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ExtensionPreload {
+    pub preload: Preload,
+    /// The libraries to preload, by the name `LOAD` takes, e.g. `foo` for `foo.so`
+    pub libraries: Vec<String>,
+    pub evidence: BTreeSet<String>,
+}
+
+impl ExtensionPreload {
+    /// Analyze every shared object of `archive`, leaving out those which can't be parsed
+    pub fn from_archive(extension_name: &str, archive: &Archive) -> Self {
+        let mut extension_preload = Self::default();
+
+        for entry in archive.shared_objects() {
+            let requirement = match PreloadRequirement::analyze(&entry.contents) {
+                Ok(requirement) => requirement,
+                Err(err) => {
+                    eprintln!(
+                        "{extension_name}: not checking whether {} has to be preloaded: {err:#}",
+                        entry.path.display()
+                    );
+                    continue;
+                }
+            };
+            if requirement.preload == Preload::None {
+                continue;
+            }
+
+            let library = entry
+                .path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split(".so").next())
+                .unwrap_or_default();
+            extension_preload.libraries.push(library.to_owned());
+            extension_preload.preload = extension_preload.preload.max(requirement.preload);
+            extension_preload.evidence.extend(requirement.evidence);
+        }
+
+        extension_preload.libraries.sort_unstable();
+        extension_preload.libraries.dedup();
+
+        extension_preload
+    }
+
+    /// The `X-Postgres-Preload` control field, if the extension must be preloaded
+    pub fn control_field(&self) -> Option<&'static str> {
+        match self.preload {
+            Preload::None => None,
+            Preload::Session => Some("session"),
+            Preload::Restart => Some("yes"),
+        }
+    }
+
+    /// A commented-out configuration snippet enabling the extension's libraries, to be copied
+    /// into the `conf.d` directory of a cluster of PostgreSQL `pg_major`
+    pub fn conf_snippet(&self, extension_name: &str, pg_major: u16) -> Option<String> {
+        let setting = match self.preload {
+            Preload::None => return None,
+            Preload::Session => "session_preload_libraries",
+            Preload::Restart => "shared_preload_libraries",
+        };
+        let evidence: Vec<_> = self.evidence.iter().map(String::as_str).collect();
+        let restart = if self.preload == Preload::Restart {
+            ", then restart PostgreSQL"
+        } else {
+            ""
+        };
+
+        Some(format!(
+            "# {extension_name} has to be preloaded, as its libraries use {}.\n\
+             # Copy this file into the conf.d directory of the cluster, e.g.\n\
+             # /etc/postgresql/{pg_major}/main/conf.d/, and uncomment the line below,\n\
+             # merging it with any other {setting} setting{restart}.\n\
+             #{setting} = '{}'\n",
+            evidence.join(", "),
+            self.libraries.join(",")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::unarchiver::{Archive, Entry, EntryKind};
+
+    use super::{ExtensionPreload, Preload, PreloadRequirement};
+
+    /// Built by `tests/fixtures/build.sh`: reads `planner_hook`
+    const SESSION_HOOK: &[u8] = include_bytes!("../tests/fixtures/session_hook.so");
+    /// Built by `tests/fixtures/build.sh`: reads `planner_hook` and `shmem_request_hook`
+    const SHMEM_HOOK: &[u8] = include_bytes!("../tests/fixtures/shmem_hook.so");
+    /// Built by `tests/fixtures/build.sh`: uses no server symbols
+    const MODULE: &[u8] = include_bytes!("../tests/fixtures/module.so");
+
+    fn archive(files: &[(&str, &[u8])]) -> Archive {
+        let mut archive = Archive::default();
+        for (path, contents) in files {
+            archive.push(Entry {
+                path: format!("lib/{path}").into(),
+                contents: contents.to_vec(),
+                mtime: 0,
+                kind: EntryKind::Regular,
+                mode: 0o755,
+                link_target: None,
+            });
+        }
+        archive
+    }
+
+    #[test]
+    fn analyze() {
+        let session = PreloadRequirement::analyze(SESSION_HOOK).unwrap();
+        assert_eq!(session.preload, Preload::Session);
+        assert_eq!(session.evidence, ["planner_hook".to_owned()].into());
+        assert_eq!(
+            session.to_string(),
+            "must be loaded into every session (planner_hook)"
+        );
+
+        let restart = PreloadRequirement::analyze(SHMEM_HOOK).unwrap();
+        assert_eq!(restart.preload, Preload::Restart);
+        assert_eq!(
+            restart.evidence,
+            ["planner_hook".to_owned(), "shmem_request_hook".to_owned()].into()
+        );
+
+        let none = PreloadRequirement::analyze(MODULE).unwrap();
+        assert_eq!(none, PreloadRequirement::default());
+        assert_eq!(none.to_string(), "loaded on demand");
+    }
+
+    #[test]
+    fn session() {
+        let preload = ExtensionPreload::from_archive(
+            "myext",
+            &archive(&[("myext.so", SESSION_HOOK), ("helper.so", MODULE)]),
+        );
+
+        assert_eq!(preload.preload, Preload::Session);
+        assert_eq!(preload.libraries, ["myext"]);
+        assert_eq!(preload.control_field(), Some("session"));
+        assert_eq!(
+            preload.conf_snippet("myext", 16).unwrap(),
+            "# myext has to be preloaded, as its libraries use planner_hook.\n\
+             # Copy this file into the conf.d directory of the cluster, e.g.\n\
+             # /etc/postgresql/16/main/conf.d/, and uncomment the line below,\n\
+             # merging it with any other session_preload_libraries setting.\n\
+             #session_preload_libraries = 'myext'\n"
+        );
+    }
+
+    #[test]
+    fn restart() {
+        let preload = ExtensionPreload::from_archive(
+            "myext",
+            &archive(&[
+                ("myext.so.1", SESSION_HOOK),
+                ("myext_bgw.so", SHMEM_HOOK),
+                ("broken.so", b"\x7fELF, but truncated"),
+            ]),
+        );
+
+        assert_eq!(preload.preload, Preload::Restart);
+        assert_eq!(preload.libraries, ["myext", "myext_bgw"]);
+        assert_eq!(preload.control_field(), Some("yes"));
+        assert!(preload.conf_snippet("myext", 16).unwrap().ends_with(
+            "any other shared_preload_libraries setting, then restart PostgreSQL.\n\
+                        #shared_preload_libraries = 'myext,myext_bgw'\n"
+        ));
+    }
+
+    #[test]
+    fn on_demand() {
+        let preload = ExtensionPreload::from_archive("myext", &archive(&[("myext.so", MODULE)]));
+
+        assert_eq!(preload, ExtensionPreload::default());
+        assert_eq!(preload.control_field(), None);
+        assert_eq!(preload.conf_snippet("myext", 16), None);
+    }
+}
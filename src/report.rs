@@ -0,0 +1,191 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use crate::{
+    dependencies::Dependencies,
+    mappings::{SonameMappings, UnknownSoname},
+    packages_index::PackagesIndex,
+    Result,
+};
+
+/// The sonames whose package is unknown, along with the extensions they keep from being packaged
+#[derive(Default, Debug)]
+pub struct UnknownSonameReport {
+    blocked: BTreeMap<String, BTreeSet<String>>,
+    /// How many extensions were analyzed
+    analyzed: usize,
+}
+
+impl UnknownSonameReport {
+    /// Record the unknown sonames of an extension, unless its manifest declares its packages
+    pub fn add(
+        &mut self,
+        extension_name: &str,
+        dependencies: &mut Dependencies,
+        mappings: Option<&SonameMappings>,
+    ) {
+        self.analyzed += 1;
+
+        if let Some(mappings) = mappings {
+            dependencies.apply_mappings(mappings);
+        }
+        if dependencies.all_known() {
+            return;
+        }
+
+        for soname in dependencies.unknown_sonames() {
+            self.blocked
+                .entry(soname.to_owned())
+                .or_default()
+                .insert(extension_name.to_owned());
+        }
+    }
+
+    /// The unknown sonames, blocking the most extensions first, with package suggestions
+    fn ranked(
+        &self,
+        mappings: Option<&SonameMappings>,
+        index: Option<&PackagesIndex>,
+    ) -> Vec<(UnknownSoname, &BTreeSet<String>)> {
+        let mut ranked: Vec<_> = self
+            .blocked
+            .iter()
+            .map(|(soname, extensions)| (UnknownSoname::new(soname, mappings, index), extensions))
+            .collect();
+        // Stable, so that sonames blocking as many extensions stay sorted by name
+        ranked.sort_by_key(|(_, extensions)| std::cmp::Reverse(extensions.len()));
+
+        ranked
+    }
+
+    pub fn render(
+        &self,
+        mappings: Option<&SonameMappings>,
+        index: Option<&PackagesIndex>,
+    ) -> Result<String> {
+        let blocked: BTreeSet<&String> = self.blocked.values().flatten().collect();
+        let mut report = String::with_capacity(1024);
+        writeln!(
+            report,
+            "{} unknown soname(s) block {} of {} extension(s)",
+            self.blocked.len(),
+            blocked.len(),
+            self.analyzed
+        )?;
+
+        for (unknown, extensions) in self.ranked(mappings, index) {
+            let extensions: Vec<_> = extensions.iter().map(String::as_str).collect();
+            writeln!(
+                report,
+                "{:>4}  {}: {}",
+                extensions.len(),
+                unknown.soname,
+                extensions.join(", ")
+            )?;
+            writeln!(report, "      {unknown}")?;
+        }
+
+        Ok(report)
+    }
+
+    /// `soname package` lines in the format of `libraries-found`, naming the best suggestion or
+    /// `?`, so that once checked they can be used as `--mappings`. With `commented`, the lines
+    /// are commented out like the stubs of `--write-missing`, until each guess is checked.
+    pub fn render_mappings(
+        &self,
+        mappings: Option<&SonameMappings>,
+        index: Option<&PackagesIndex>,
+        commented: bool,
+    ) -> String {
+        self.ranked(mappings, index)
+            .into_iter()
+            .map(|(unknown, _)| {
+                if commented {
+                    unknown.stub()
+                } else {
+                    unknown.mapping()
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dependencies::Dependencies, mappings::SonameMappings};
+
+    use super::UnknownSonameReport;
+
+    fn dependencies(sonames: &[&str]) -> Dependencies {
+        let mut dependencies = Dependencies::new();
+        for soname in sonames {
+            dependencies.add(soname);
+        }
+
+        dependencies
+    }
+
+    fn report(mappings: Option<&SonameMappings>) -> UnknownSonameReport {
+        let mut report = UnknownSonameReport::default();
+        let extensions: [(&str, &[&str]); 4] = [
+            ("ext_a", &["libfoo.so.1", "libbar.so.2", "libc.so.6"]),
+            ("ext_b", &["libfoo.so.1"]),
+            (
+                "ext_c",
+                &["libfoo.so.1", "libbar.so.2", "libbaz.so.3", "libaaa.so.1"],
+            ),
+            ("ext_d", &["libm.so.6"]),
+        ];
+        for (extension, sonames) in extensions {
+            report.add(extension, &mut dependencies(sonames), mappings);
+        }
+
+        // The manifest declaring its packages, nothing is unknown
+        let mut declared = dependencies(&["libqux.so.1"]);
+        declared.declare("ext_e", &["libqux1".to_owned()]);
+        report.add("ext_e", &mut declared, mappings);
+
+        report
+    }
+
+    #[test]
+    fn ranked_by_impact() {
+        let report = report(None);
+
+        assert_eq!(
+            report.render(None, None).unwrap(),
+            "4 unknown soname(s) block 3 of 5 extension(s)\n\
+             \x20  3  libfoo.so.1: ext_a, ext_b, ext_c\n\
+             \x20     libfoo.so.1: did you mean libfoo1?\n\
+             \x20  2  libbar.so.2: ext_a, ext_c\n\
+             \x20     libbar.so.2: did you mean libbar2?\n\
+             \x20  1  libaaa.so.1: ext_c\n\
+             \x20     libaaa.so.1: did you mean libaaa1?\n\
+             \x20  1  libbaz.so.3: ext_c\n\
+             \x20     libbaz.so.3: did you mean libbaz3?\n"
+        );
+        assert_eq!(
+            report.render_mappings(None, None, false),
+            "libfoo.so.1 libfoo1\nlibbar.so.2 libbar2\nlibaaa.so.1 libaaa1\nlibbaz.so.3 libbaz3\n"
+        );
+        assert!(report
+            .render_mappings(None, None, true)
+            .starts_with("# libfoo.so.1 libfoo1\n# libbar.so.2 libbar2\n"));
+    }
+
+    #[test]
+    fn exported_mappings_are_used() {
+        let exported = report(None).render_mappings(None, None, false);
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs_err::write(file.path(), exported).unwrap();
+        let mappings = SonameMappings::from_file(file.path(), false).unwrap();
+
+        let report = report(Some(&mappings));
+        assert_eq!(
+            report.render(Some(&mappings), None).unwrap(),
+            "0 unknown soname(s) block 0 of 5 extension(s)\n"
+        );
+    }
+}
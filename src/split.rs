@@ -0,0 +1,84 @@
+use serde::Deserialize;
+
+/// The binary packages an extension is split into, following the convention of Debian's own
+/// PostgreSQL extension packages
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SubPackage {
+    /// Shared libraries, SQL scripts and everything else needed at runtime
+    #[default]
+    Main,
+    /// Server headers
+    Dev,
+    /// Documentation
+    Doc,
+    /// LLVM bitcode used by PostgreSQL's JIT compiler
+    Jit,
+    /// Debug information split out of the shared objects, shipped as a `.ddeb`
+    Dbgsym,
+}
+
+impl SubPackage {
+    /// The name of this package, given the name of the main package
+    pub fn package_name(self, main_package: &str) -> String {
+        let suffix = match self {
+            SubPackage::Main => "",
+            SubPackage::Dev => "-dev",
+            SubPackage::Doc => "-doc",
+            SubPackage::Jit => "-jit",
+            SubPackage::Dbgsym => "-dbgsym",
+        };
+
+        format!("{main_package}{suffix}")
+    }
+
+    pub fn section(self) -> &'static str {
+        match self {
+            SubPackage::Main | SubPackage::Jit => "database",
+            SubPackage::Dev => "libdevel",
+            SubPackage::Doc => "doc",
+            SubPackage::Dbgsym => "debug",
+        }
+    }
+
+    /// Debug symbol packages are `.ddeb`s, so that archives can keep them apart
+    pub fn file_extension(self) -> &'static str {
+        match self {
+            SubPackage::Dbgsym => "ddeb",
+            _ => "deb",
+        }
+    }
+
+    /// The architecture of this package, given the one of the extension's shared libraries
+    pub fn architecture(self, architecture: &str) -> &str {
+        match self {
+            SubPackage::Doc => "all",
+            _ => architecture,
+        }
+    }
+
+    /// What this package holds, appended to the extension's description
+    pub fn description_suffix(self) -> Option<&'static str> {
+        match self {
+            SubPackage::Main => None,
+            SubPackage::Dev => Some("development files"),
+            SubPackage::Doc => Some("documentation"),
+            SubPackage::Jit => Some("JIT bitcode"),
+            SubPackage::Dbgsym => Some("debug symbols"),
+        }
+    }
+
+    /// Relations of this package to the PostgreSQL server and to the main package of the split.
+    /// The main package's shared library dependencies are not included.
+    pub fn depends(self, main_package: &str, version: &str, pg_major: u16) -> Vec<String> {
+        let on_main_package = format!("{main_package} (= {version})");
+
+        match self {
+            SubPackage::Main => vec![format!("postgresql-{pg_major}")],
+            SubPackage::Dev => vec![on_main_package, format!("postgresql-server-dev-{pg_major}")],
+            SubPackage::Doc => Vec::new(),
+            SubPackage::Jit => vec![on_main_package, format!("postgresql-{pg_major}-jit-llvm")],
+            SubPackage::Dbgsym => vec![on_main_package],
+        }
+    }
+}
@@ -0,0 +1,464 @@
+use std::{ops::Not, path::PathBuf};
+
+use anyhow::{bail, Context};
+use goblin::{
+    container::{Container, Ctx},
+    elf::{
+        note::NT_GNU_BUILD_ID,
+        section_header::{SHF_ALLOC, SHF_INFO_LINK, SHT_NOBITS, SHT_NOTE},
+        Elf, SectionHeader,
+    },
+};
+use scroll::{Endian, Pwrite};
+
+use crate::Result;
+
+/// Where debuggers and debuginfod clients look for detached debug information
+pub const DEBUG_DIR: &str = "/usr/lib/debug/.build-id";
+
+/// What happens to a section when an ELF file is rewritten
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SectionAction {
+    Keep,
+    /// Keep the header, but not the contents, as `--only-keep-debug` does for code and data
+    KeepHeader,
+    Remove,
+}
+
+/// A shared object whose debug information was moved into a file of its own
+pub struct SplitDebugInfo {
+    pub build_id: String,
+    /// The shared object, without its debug sections
+    pub stripped: Vec<u8>,
+    /// An ELF file holding the debug sections and symbol table of the shared object
+    pub debug_file: Vec<u8>,
+}
+
+impl SplitDebugInfo {
+    /// The path of the debug file, e.g. `/usr/lib/debug/.build-id/ab/cdef0123.debug`
+    pub fn debug_path(&self) -> PathBuf {
+        let (directory, file) = self.build_id.split_at(2);
+
+        PathBuf::from(format!("{DEBUG_DIR}/{directory}/{file}.debug"))
+    }
+}
+
+/// Sections holding DWARF debug information, possibly compressed
+fn is_debug_section(name: &str) -> bool {
+    name.starts_with(".debug_") || name.starts_with(".zdebug_")
+}
+
+/// The GNU build-id of an ELF file as a hex string, if it has one
+pub fn build_id(elf: &Elf, bytes: &[u8]) -> Option<String> {
+    let note = elf
+        .iter_note_sections(bytes, Some(".note.gnu.build-id"))?
+        .filter_map(|note| note.ok())
+        .find(|note| note.n_type == NT_GNU_BUILD_ID && note.name == "GNU")?;
+
+    let build_id = note.desc.iter().map(|byte| format!("{byte:02x}")).collect();
+
+    Some(build_id)
+}
+
+/// Move the debug sections of a shared object into a separate file, keyed by build-id.
+///
+/// Returns `None` if the object holds no debug information.
+pub fn split_debug_info(bytes: &[u8]) -> Result<Option<SplitDebugInfo>> {
+    let elf = Elf::parse(bytes)?;
+
+    let has_debug_info = elf
+        .section_headers
+        .iter()
+        .any(|section| is_debug_section(section_name(&elf, section)));
+    if has_debug_info.not() {
+        return Ok(None);
+    }
+
+    let build_id = build_id(&elf, bytes).context("Object has debug info but no build-id")?;
+    anyhow::ensure!(build_id.len() > 2, "Build-id {build_id} is too short");
+
+    let stripped = rewrite(&elf, bytes, true, |name, _| {
+        if is_debug_section(name) {
+            SectionAction::Remove
+        } else {
+            SectionAction::Keep
+        }
+    })?;
+
+    // Like `objcopy --only-keep-debug`: every header survives so that section indices still
+    // match, but only notes and non-allocated sections, such as `.symtab`, keep their contents
+    let debug_file = rewrite(&elf, bytes, false, |_, section| {
+        let is_allocated = section.sh_flags & u64::from(SHF_ALLOC) != 0;
+
+        if is_allocated && section.sh_type != SHT_NOTE {
+            SectionAction::KeepHeader
+        } else {
+            SectionAction::Keep
+        }
+    })?;
+
+    // Make sure we're not shipping something we broke
+    Elf::parse(&stripped).context("Stripped object does not parse")?;
+    Elf::parse(&debug_file).context("Debug file does not parse")?;
+
+    Ok(Some(SplitDebugInfo {
+        build_id,
+        stripped,
+        debug_file,
+    }))
+}
+
+/// Remove the symbol table, `.comment` and debug sections of a shared object, like
+/// `strip --strip-unneeded` would. The dynamic symbol table is left untouched.
+pub fn strip(bytes: &[u8]) -> Result<Vec<u8>> {
+    let elf = Elf::parse(bytes)?;
+
+    let stripped = rewrite(&elf, bytes, true, |name, _| {
+        if matches!(name, ".symtab" | ".strtab" | ".comment") || is_debug_section(name) {
+            SectionAction::Remove
+        } else {
+            SectionAction::Keep
+        }
+    })?;
+
+    // Make sure the library still works the same for the dynamic linker
+    let stripped_elf = Elf::parse(&stripped).context("Stripped object does not parse")?;
+    if dynamic_symbols(&stripped_elf) != dynamic_symbols(&elf) {
+        bail!("Stripping changed the dynamic symbols");
+    }
+
+    Ok(stripped)
+}
+
+/// Name, value, type and binding, and section of every dynamic symbol
+fn dynamic_symbols<'a>(elf: &'a Elf) -> Vec<(&'a str, u64, u8, usize)> {
+    elf.dynsyms
+        .iter()
+        .map(|symbol| {
+            let name = elf.dynstrtab.get_at(symbol.st_name).unwrap_or("");
+
+            (name, symbol.st_value, symbol.st_info, symbol.st_shndx)
+        })
+        .collect()
+}
+
+fn section_name<'a>(elf: &'a Elf, section: &SectionHeader) -> &'a str {
+    elf.shdr_strtab.get_at(section.sh_name).unwrap_or("")
+}
+
+fn context(elf: &Elf) -> Ctx {
+    let container = if elf.is_64 {
+        Container::Big
+    } else {
+        Container::Little
+    };
+
+    Ctx::new(container, Endian::from(elf.little_endian))
+}
+
+fn align(bytes: &mut Vec<u8>, alignment: u64) {
+    let alignment = alignment.max(1) as usize;
+    let padded_len = bytes.len().div_ceil(alignment) * alignment;
+
+    bytes.resize(padded_len, 0);
+}
+
+/// Write a copy of an ELF file in which every section is handled as `action` decides.
+///
+/// With `keep_program` set, everything the program headers cover is copied verbatim, so only
+/// non-allocated sections may be removed. Otherwise, the program headers are dropped, which only
+/// makes sense for files never meant to be loaded, such as detached debug information.
+fn rewrite(
+    elf: &Elf,
+    bytes: &[u8],
+    keep_program: bool,
+    action: impl Fn(&str, &SectionHeader) -> SectionAction,
+) -> Result<Vec<u8>> {
+    let ctx = context(elf);
+    let header_size = goblin::elf::Header::size(ctx);
+    let shstrndx = usize::from(elf.header.e_shstrndx);
+
+    let actions: Vec<_> = elf
+        .section_headers
+        .iter()
+        .enumerate()
+        .map(|(idx, section)| match idx {
+            0 => SectionAction::Keep,
+            idx if idx == shstrndx => SectionAction::Keep,
+            _ => action(section_name(elf, section), section),
+        })
+        .collect();
+
+    let mut output = if keep_program {
+        let mut program_end = header_size as u64;
+        for segment in &elf.program_headers {
+            program_end = program_end.max(segment.p_offset + segment.p_filesz);
+        }
+        program_end = program_end.max(
+            elf.header.e_phoff + u64::from(elf.header.e_phnum) * elf.header.e_phentsize as u64,
+        );
+
+        for (section, action) in elf.section_headers.iter().zip(&actions) {
+            let is_allocated = section.sh_flags & u64::from(SHF_ALLOC) != 0;
+            if is_allocated && *action != SectionAction::Keep {
+                bail!(
+                    "Can't drop {}, which is loaded at runtime",
+                    section_name(elf, section)
+                );
+            }
+            if is_allocated && section.sh_type != SHT_NOBITS {
+                program_end = program_end.max(section.sh_offset + section.sh_size);
+            }
+        }
+
+        bytes
+            .get(..program_end as usize)
+            .context("Segments extend past the end of the file")?
+            .to_vec()
+    } else {
+        vec![0; header_size]
+    };
+
+    // Indices of the sections in the rewritten file
+    let mut new_indices = Vec::with_capacity(actions.len());
+    let mut next_index = 0;
+    for action in &actions {
+        if *action == SectionAction::Remove {
+            new_indices.push(None);
+        } else {
+            new_indices.push(Some(next_index));
+            next_index += 1;
+        }
+    }
+
+    // Symbols refer to the sections they're defined in by index, which removing a section
+    // before theirs would invalidate
+    let last_allocated = elf
+        .section_headers
+        .iter()
+        .rposition(|section| section.sh_flags & u64::from(SHF_ALLOC) != 0)
+        .unwrap_or(0);
+    let first_removed = actions
+        .iter()
+        .position(|action| *action == SectionAction::Remove);
+    if first_removed.is_some_and(|first_removed| first_removed < last_allocated) {
+        bail!("Sections to remove come before loaded sections");
+    }
+
+    let mut section_headers = Vec::with_capacity(next_index);
+    for ((section, action), new_index) in elf.section_headers.iter().zip(&actions).zip(&new_indices)
+    {
+        if new_index.is_none() {
+            continue;
+        }
+
+        let mut section = section.clone();
+        let has_contents = section.sh_type != SHT_NOBITS && section.sh_size > 0;
+        let is_allocated = section.sh_flags & u64::from(SHF_ALLOC) != 0;
+
+        match action {
+            SectionAction::KeepHeader => {
+                section.sh_type = SHT_NOBITS;
+                section.sh_offset = output.len() as u64;
+            }
+            SectionAction::Keep if has_contents && (keep_program && is_allocated).not() => {
+                let start = section.sh_offset as usize;
+                let contents = bytes
+                    .get(start..start + section.sh_size as usize)
+                    .with_context(|| {
+                        format!(
+                            "{} extends past the end of the file",
+                            section_name(elf, &section)
+                        )
+                    })?;
+
+                align(&mut output, section.sh_addralign);
+                section.sh_offset = output.len() as u64;
+                output.extend_from_slice(contents);
+            }
+            _ => {}
+        }
+
+        let remap = |idx: u32| {
+            new_indices
+                .get(idx as usize)
+                .copied()
+                .flatten()
+                .unwrap_or(0) as u32
+        };
+        if section.sh_link != 0 {
+            section.sh_link = remap(section.sh_link);
+        }
+        if section.sh_flags & u64::from(SHF_INFO_LINK) != 0 {
+            section.sh_info = remap(section.sh_info);
+        }
+
+        section_headers.push(section);
+    }
+
+    align(&mut output, if elf.is_64 { 8 } else { 4 });
+    let section_headers_offset = output.len();
+    let section_header_size = SectionHeader::size(ctx);
+    output.resize(
+        section_headers_offset + section_header_size * section_headers.len(),
+        0,
+    );
+    for (idx, section) in section_headers.into_iter().enumerate() {
+        output.pwrite_with(
+            section,
+            section_headers_offset + idx * section_header_size,
+            ctx,
+        )?;
+    }
+
+    let mut header = elf.header;
+    header.e_shoff = section_headers_offset as u64;
+    header.e_shnum = next_index as u16;
+    header.e_shstrndx = new_indices[shstrndx].context("Section names were removed")? as u16;
+    if keep_program.not() {
+        header.e_phoff = 0;
+        header.e_phnum = 0;
+    }
+    output.pwrite_with(header, 0, ctx.le)?;
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Not;
+
+    use goblin::elf::{section_header::SHT_NOBITS, Elf};
+
+    use super::{
+        build_id, dynamic_symbols, rewrite, section_name, split_debug_info, strip, SectionAction,
+    };
+
+    /// Built by `tests/fixtures/build.sh`, with debug info and a build-id
+    const HARDENED: &[u8] = include_bytes!("../tests/fixtures/libhardened.so");
+    /// Like the above, but with a single-byte build-id
+    const SHORT_BUILD_ID: &[u8] = include_bytes!("../tests/fixtures/libshortid.so");
+
+    fn section_names<'a>(elf: &'a Elf) -> Vec<&'a str> {
+        elf.section_headers
+            .iter()
+            .map(|section| section_name(elf, section))
+            .collect()
+    }
+
+    #[test]
+    fn split() {
+        let original = Elf::parse(HARDENED).unwrap();
+        let split = split_debug_info(HARDENED)
+            .unwrap()
+            .expect("the fixture has debug info");
+
+        assert_eq!(split.build_id.len(), 40);
+        assert_eq!(
+            Some(&split.build_id),
+            build_id(&original, HARDENED).as_ref()
+        );
+        assert_eq!(
+            split.debug_path().display().to_string(),
+            format!(
+                "/usr/lib/debug/.build-id/{}/{}.debug",
+                &split.build_id[..2],
+                &split.build_id[2..]
+            )
+        );
+
+        let stripped = Elf::parse(&split.stripped).unwrap();
+        let names = section_names(&stripped);
+        assert!(
+            names.iter().all(|name| name.starts_with(".debug_").not()),
+            "{names:?}"
+        );
+        assert!(names.contains(&".symtab"), "{names:?}");
+        assert_eq!(dynamic_symbols(&stripped), dynamic_symbols(&original));
+        assert_eq!(stripped.program_headers, original.program_headers);
+        assert!(split.stripped.len() < HARDENED.len());
+        // Nothing is left to split off
+        assert!(split_debug_info(&split.stripped).unwrap().is_none());
+
+        let debug_file = Elf::parse(&split.debug_file).unwrap();
+        assert!(debug_file.program_headers.is_empty());
+        assert_eq!(section_names(&debug_file), section_names(&original));
+        for section in &debug_file.section_headers {
+            let name = section_name(&debug_file, section);
+            if name.starts_with(".debug_") || name == ".symtab" {
+                assert_ne!(section.sh_type, SHT_NOBITS, "{name} lost its contents");
+            }
+            if name == ".text" {
+                assert_eq!(section.sh_type, SHT_NOBITS, "{name} kept its contents");
+            }
+        }
+        assert_eq!(
+            build_id(&debug_file, &split.debug_file),
+            Some(split.build_id)
+        );
+    }
+
+    #[test]
+    fn short_build_id() {
+        let err = split_debug_info(SHORT_BUILD_ID).err().unwrap();
+
+        assert_eq!(err.to_string(), "Build-id 01 is too short");
+    }
+
+    #[test]
+    fn removing_sections_before_loaded_ones() {
+        let elf = Elf::parse(HARDENED).unwrap();
+        let err = rewrite(&elf, HARDENED, false, |name, _| match name {
+            ".dynsym" => SectionAction::Remove,
+            _ => SectionAction::Keep,
+        })
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            "Sections to remove come before loaded sections"
+        );
+    }
+
+    #[test]
+    fn dropping_loaded_sections() {
+        let elf = Elf::parse(HARDENED).unwrap();
+        let err = rewrite(&elf, HARDENED, true, |name, _| match name {
+            ".text" => SectionAction::KeepHeader,
+            _ => SectionAction::Keep,
+        })
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            "Can't drop .text, which is loaded at runtime"
+        );
+    }
+
+    #[test]
+    fn strip_unneeded() {
+        let original = Elf::parse(HARDENED).unwrap();
+        let stripped_bytes = strip(HARDENED).unwrap();
+        let stripped = Elf::parse(&stripped_bytes).unwrap();
+
+        let names = section_names(&stripped);
+        for removed in [".symtab", ".strtab", ".comment"] {
+            assert!(section_names(&original).contains(&removed));
+            assert!(names.contains(&removed).not(), "{removed} is left");
+        }
+        assert!(
+            names.iter().all(|name| name.starts_with(".debug_").not()),
+            "{names:?}"
+        );
+        assert!(names.contains(&".dynsym") && names.contains(&".text"));
+        assert_eq!(dynamic_symbols(&stripped), dynamic_symbols(&original));
+        assert_eq!(stripped.program_headers, original.program_headers);
+        assert_eq!(stripped.libraries, original.libraries);
+        assert!(stripped_bytes.len() < HARDENED.len());
+
+        // Stripping is idempotent
+        assert_eq!(strip(&stripped_bytes).unwrap(), stripped_bytes);
+    }
+}
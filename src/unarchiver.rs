@@ -1,17 +1,18 @@
 use std::{
-    ffi::OsStr,
     io::{Cursor, Read},
-    path::PathBuf,
-    time::{SystemTime, UNIX_EPOCH},
+    path::{Path, PathBuf},
 };
 
 use flate2::read::GzDecoder;
 use tar::EntryType;
 
-use crate::Result;
+use anyhow::Context;
+
+use crate::{utils, Result};
 
 pub struct Unarchiver;
 
+#[derive(Default)]
 pub struct Archive {
     entries: Vec<Entry>,
 }
@@ -21,37 +22,76 @@ impl Archive {
         self.entries.iter().filter(|entry| entry.is_shared_object())
     }
 
+    pub fn shared_objects_mut(&mut self) -> impl Iterator<Item = &mut Entry> {
+        self.entries
+            .iter_mut()
+            .filter(|entry| entry.is_shared_object())
+    }
+
     pub fn all_entries(&self) -> &[Entry] {
         &self.entries
     }
+
+    pub fn push(&mut self, entry: Entry) {
+        self.entries.push(entry);
+    }
 }
 
+/// The kinds of archive entries we carry over into packages
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EntryKind {
+    Regular,
+    Directory,
+    Symlink,
+}
+
+#[derive(Clone)]
 pub struct Entry {
     pub path: PathBuf,
     pub contents: Vec<u8>,
+    /// Modification time recorded in the source archive
+    pub mtime: u64,
+    pub kind: EntryKind,
+    /// Permission bits recorded in the source archive
+    pub mode: u32,
+    /// What this entry points to, if it's a symlink
+    pub link_target: Option<PathBuf>,
 }
 
 impl Entry {
+    /// Whether this is a regular file named like a shared object, e.g. `foo.so` or `libfoo.so.1`
     pub fn is_shared_object(&self) -> bool {
-        matches!(self.extension(), Some(b"so"))
+        self.kind == EntryKind::Regular && self.has_shared_object_name()
     }
 
-    pub fn extension(&self) -> Option<&[u8]> {
-        use std::os::unix::ffi::OsStrExt;
+    /// Whether this entry is named like a shared object, regardless of its kind
+    pub fn has_shared_object_name(&self) -> bool {
+        self.path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .is_some_and(utils::is_shared_object_name)
+    }
 
-        self.path.extension().map(OsStr::as_bytes)
+    /// Whether any of the executable bits are set in the source archive
+    pub fn is_executable(&self) -> bool {
+        self.mode & 0o111 != 0
     }
 
-    pub fn tar_header(&self) -> tar::Header {
+    pub fn tar_header(&self, mtime: u64, mode: u32) -> tar::Header {
         let mut header = tar::Header::new_gnu();
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
 
-        header.set_mode(0o644);
-        header.set_mtime(now.as_secs());
+        let entry_type = match self.kind {
+            EntryKind::Regular => EntryType::Regular,
+            EntryKind::Directory => EntryType::Directory,
+            EntryKind::Symlink => EntryType::Symlink,
+        };
+
+        header.set_mode(mode);
+        header.set_mtime(mtime);
         header.set_uid(0);
         header.set_gid(0);
         header.set_size(self.contents.len() as u64);
-        header.set_entry_type(EntryType::Regular);
+        header.set_entry_type(entry_type);
 
         header.set_cksum();
         header
@@ -65,25 +105,58 @@ impl Unarchiver {
 
         let mut archive = tar::Archive::new(Cursor::new(buf));
 
-        let mut entries = Vec::new();
+        let mut entries: Vec<Entry> = Vec::new();
 
         for maybe_entry in archive.entries()? {
             let mut entry = maybe_entry?;
             let header = entry.header();
             let entry_size = header.entry_size().unwrap_or(12500);
-
-            match header.entry_type() {
-                EntryType::Regular => {}
+            let mtime = header.mtime().unwrap_or(0);
+            let mode = header.mode().unwrap_or(0o644) & 0o7777;
+
+            let kind = match header.entry_type() {
+                EntryType::Regular | EntryType::Continuous => EntryKind::Regular,
+                EntryType::Directory => EntryKind::Directory,
+                EntryType::Symlink => EntryKind::Symlink,
+                EntryType::Link => {
+                    // Hard links are stored as a copy of the file they point to
+                    let path: PathBuf = entry.path()?.into();
+                    let target = entry
+                        .link_name()?
+                        .with_context(|| format!("Hard link {} has no target", path.display()))?;
+                    let linked = Self::find_entry(&entries, &target).with_context(|| {
+                        format!(
+                            "Hard link {} points to missing entry {}",
+                            path.display(),
+                            target.display()
+                        )
+                    })?;
+
+                    let copy = Entry {
+                        path,
+                        contents: linked.contents.clone(),
+                        mtime,
+                        kind: EntryKind::Regular,
+                        mode: linked.mode,
+                        link_target: None,
+                    };
+                    entries.push(copy);
+                    continue;
+                }
                 other => {
                     eprintln!(
-                        "decompressing: Found a {:?} file, expected Regular. Ignoring",
+                        "decompressing: Found a {:?} file, which can't be packaged. Ignoring",
                         other
                     );
                     continue;
                 }
-            }
+            };
 
             let path = entry.path()?.into();
+            let link_target = match kind {
+                EntryKind::Symlink => entry.link_name()?.map(Into::into),
+                _ => None,
+            };
 
             let contents = {
                 let mut buf = Vec::with_capacity(entry_size as usize);
@@ -92,9 +165,26 @@ impl Unarchiver {
                 buf
             };
 
-            entries.push(Entry { path, contents });
+            entries.push(Entry {
+                path,
+                contents,
+                mtime,
+                kind,
+                mode,
+                link_target,
+            });
         }
 
         Ok(Archive { entries })
     }
+
+    fn find_entry<'a>(entries: &'a [Entry], path: &Path) -> Option<&'a Entry> {
+        fn normalized(path: &Path) -> &Path {
+            path.strip_prefix(".").unwrap_or(path)
+        }
+
+        entries
+            .iter()
+            .find(|entry| normalized(&entry.path) == normalized(path))
+    }
 }
@@ -0,0 +1,249 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    ops::Not,
+};
+
+use crate::{
+    unarchiver::{Archive, EntryKind},
+    utils,
+};
+
+/// The versions of an extension and the SQL scripts between them: `foo--1.0.sql` installs
+/// 1.0, and `foo--1.0--1.1.sql` upgrades 1.0 to 1.1
+#[derive(Default, Debug)]
+pub struct UpgradeGraph {
+    /// Versions with an install script
+    installs: BTreeSet<String>,
+    /// The versions each version can be upgraded to with a single script
+    upgrades: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// What `CREATE EXTENSION` and `ALTER EXTENSION ... UPDATE` can do with an extension's scripts
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UpgradeReport {
+    pub extension: String,
+    pub default_version: String,
+    /// Whether an install script, or one followed by upgrade scripts, leads to `default_version`
+    pub installable: bool,
+    /// Versions from which `default_version` can be reached in place
+    pub upgradable_from: Vec<String>,
+    /// Versions no install script leads to
+    pub unreachable: Vec<String>,
+    /// Versions from which no chain of upgrade scripts leads to `default_version`
+    pub dead_ends: Vec<String>,
+}
+
+impl UpgradeGraph {
+    /// Build the graph from script names without the extension name and `.sql`, e.g. `1.0`
+    /// for `foo--1.0.sql` and `1.0--1.1` for `foo--1.0--1.1.sql`
+    pub fn from_script_names<'s>(scripts: impl Iterator<Item = &'s str>) -> Self {
+        let mut graph = Self::default();
+        graph.add_script_names(scripts);
+
+        graph
+    }
+
+    fn add_script_names<'s>(&mut self, scripts: impl Iterator<Item = &'s str>) {
+        for script in scripts {
+            match script.split_once("--") {
+                Some((from, to)) => {
+                    self.upgrades
+                        .entry(from.to_owned())
+                        .or_default()
+                        .insert(to.to_owned());
+                }
+                None => {
+                    self.installs.insert(script.to_owned());
+                }
+            }
+        }
+    }
+
+    /// Build the graph of `extension` from the SQL scripts anywhere in `archive`
+    pub fn from_archive(archive: &Archive, extension: &str) -> Self {
+        let mut graph = Self::default();
+        graph.add_archive(archive, extension);
+
+        graph
+    }
+
+    /// Add the SQL scripts of `extension` anywhere in `archive`
+    pub fn add_archive(&mut self, archive: &Archive, extension: &str) {
+        let prefix = format!("{extension}--");
+
+        let scripts = archive
+            .all_entries()
+            .iter()
+            .filter(|entry| entry.kind == EntryKind::Regular)
+            .filter_map(|entry| {
+                entry
+                    .path
+                    .file_name()?
+                    .to_str()?
+                    .strip_prefix(&prefix)?
+                    .strip_suffix(".sql")
+            });
+
+        self.add_script_names(scripts);
+    }
+
+    /// Every version some script mentions
+    pub fn versions(&self) -> BTreeSet<&str> {
+        let targets = self.upgrades.values().flatten();
+
+        self.installs
+            .iter()
+            .chain(self.upgrades.keys())
+            .chain(targets)
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// The versions reachable from `starts` by following upgrade scripts, `starts` included
+    fn reachable_from<'g>(&'g self, starts: impl Iterator<Item = &'g str>) -> BTreeSet<&'g str> {
+        let mut queue: VecDeque<_> = starts.collect();
+        let mut seen: BTreeSet<_> = queue.iter().copied().collect();
+
+        while let Some(version) = queue.pop_front() {
+            for next in self.upgrades.get(version).into_iter().flatten() {
+                if seen.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        seen
+    }
+
+    /// Whether `version` has its own install script
+    pub fn can_install(&self, version: &str) -> bool {
+        self.installs.contains(version)
+    }
+
+    /// Whether `ALTER EXTENSION ... UPDATE` can go from `from` to `to`
+    pub fn can_upgrade(&self, from: &str, to: &str) -> bool {
+        self.reachable_from(std::iter::once(from)).contains(to)
+    }
+
+    pub fn analyze(&self, extension: &str, default_version: &str) -> UpgradeReport {
+        let installable = self.reachable_from(self.installs.iter().map(String::as_str));
+        let others = self
+            .versions()
+            .into_iter()
+            .filter(|version| *version != default_version);
+
+        let mut upgradable_from = Vec::new();
+        let mut dead_ends = Vec::new();
+        for version in others {
+            if self.can_upgrade(version, default_version) {
+                upgradable_from.push(version.to_owned());
+            } else {
+                dead_ends.push(version.to_owned());
+            }
+        }
+
+        let mut unreachable: Vec<_> = self
+            .versions()
+            .into_iter()
+            .filter(|version| installable.contains(version).not())
+            .map(str::to_owned)
+            .collect();
+
+        for versions in [&mut upgradable_from, &mut dead_ends, &mut unreachable] {
+            versions.sort_by(|left, right| utils::compare_versions(left, right));
+        }
+
+        UpgradeReport {
+            extension: extension.to_owned(),
+            default_version: default_version.to_owned(),
+            installable: installable.contains(default_version),
+            upgradable_from,
+            unreachable,
+            dead_ends,
+        }
+    }
+}
+
+impl UpgradeReport {
+    /// One line per problem found
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let Self {
+            extension,
+            default_version,
+            ..
+        } = self;
+
+        if self.installable.not() {
+            problems.push(format!(
+                "no SQL scripts install {extension} {default_version}"
+            ));
+        }
+        if self.unreachable.is_empty().not() {
+            problems.push(format!(
+                "no install script leads to {extension} {}",
+                self.unreachable.join(", ")
+            ));
+        }
+        if self.dead_ends.is_empty().not() {
+            problems.push(format!(
+                "{extension} {} can't be updated to {default_version}",
+                self.dead_ends.join(", ")
+            ));
+        }
+
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Not;
+
+    use super::UpgradeGraph;
+
+    fn graph(scripts: &[&str]) -> UpgradeGraph {
+        UpgradeGraph::from_script_names(scripts.iter().copied())
+    }
+
+    #[test]
+    fn upgrade_chain() {
+        let graph = graph(&["1.0", "1.0--1.1", "1.1--1.10", "1.9--1.10"]);
+        let report = graph.analyze("foo", "1.10");
+
+        assert!(report.installable);
+        assert_eq!(report.upgradable_from, ["1.0", "1.1", "1.9"]);
+        assert_eq!(report.unreachable, ["1.9"]);
+        assert!(report.dead_ends.is_empty());
+        assert_eq!(report.problems(), ["no install script leads to foo 1.9"]);
+        assert!(graph.can_upgrade("1.0", "1.10"));
+        assert!(graph.can_upgrade("1.10", "1.0").not());
+    }
+
+    #[test]
+    fn not_installable() {
+        let report = graph(&["1.0", "1.0--1.1", "2.0--1.1"]).analyze("foo", "2.0");
+
+        assert!(report.installable.not());
+        assert!(report.upgradable_from.is_empty());
+        assert_eq!(report.unreachable, ["2.0"]);
+        assert_eq!(report.dead_ends, ["1.0", "1.1"]);
+        assert_eq!(
+            report.problems(),
+            [
+                "no SQL scripts install foo 2.0",
+                "no install script leads to foo 2.0",
+                "foo 1.0, 1.1 can't be updated to 2.0",
+            ]
+        );
+    }
+
+    #[test]
+    fn single_install_script() {
+        let report = graph(&["1.0"]).analyze("foo", "1.0");
+
+        assert!(report.installable);
+        assert!(report.upgradable_from.is_empty());
+        assert!(report.problems().is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{io::Read, path::Path};
+use std::{cmp::Ordering, io::Read, ops::Not, path::Path};
 
 use crate::Result;
 
@@ -15,3 +15,145 @@ pub fn read_to_vec(path: &Path) -> Result<Vec<u8>> {
 
     Ok(buf)
 }
+
+/// Whether `file_name` is named like a shared object, e.g. `foo.so` or `libfoo.so.1.2`
+pub fn is_shared_object_name(file_name: &str) -> bool {
+    file_name.ends_with(".so")
+        || file_name
+            .find(".so.")
+            .map(|idx| &file_name[idx + 4..])
+            .is_some_and(|version| version.chars().all(|ch| ch.is_ascii_digit() || ch == '.'))
+}
+
+/// Compare two upstream version strings with `dpkg`'s ordering, numerically where possible, so
+/// that `1.10.0` is newer than `1.9.2`. A pre-release such as `1.0-beta` is compared as
+/// `1.0~beta`, so that it's older than `1.0`.
+pub fn compare_versions(left: &str, right: &str) -> Ordering {
+    let left = left.replacen('-', "~", 1);
+    let right = right.replacen('-', "~", 1);
+
+    compare_version_part(&left, &right)
+}
+
+/// Compare two Debian versions such as `1:2.34-0ubuntu3~1`, the way `dpkg` does
+pub fn compare_debian_versions(left: &str, right: &str) -> Ordering {
+    fn split(version: &str) -> (u64, &str, &str) {
+        let (epoch, rest) = match version.split_once(':') {
+            Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+            None => (0, version),
+        };
+        let (upstream, revision) = rest.rsplit_once('-').unwrap_or((rest, ""));
+
+        (epoch, upstream, revision)
+    }
+
+    let (left_epoch, left_upstream, left_revision) = split(left);
+    let (right_epoch, right_upstream, right_revision) = split(right);
+
+    left_epoch
+        .cmp(&right_epoch)
+        .then_with(|| compare_version_part(left_upstream, right_upstream))
+        .then_with(|| compare_version_part(left_revision, right_revision))
+}
+
+/// `dpkg`'s `verrevcmp`: non-digits compare with letters first and `~` before anything,
+/// even the end of the string, and runs of digits compare numerically
+fn compare_version_part(left: &str, right: &str) -> Ordering {
+    fn order(ch: Option<u8>) -> i32 {
+        match ch {
+            Some(b'~') => -1,
+            None => 0,
+            Some(ch) if ch.is_ascii_digit() => 0,
+            Some(ch) if ch.is_ascii_alphabetic() => ch as i32,
+            Some(ch) => ch as i32 + 256,
+        }
+    }
+
+    let (mut left, mut right) = (left.as_bytes(), right.as_bytes());
+
+    while left.is_empty().not() || right.is_empty().not() {
+        while left.first().is_some_and(|ch| ch.is_ascii_digit().not())
+            || right.first().is_some_and(|ch| ch.is_ascii_digit().not())
+        {
+            let ordering = order(left.first().copied()).cmp(&order(right.first().copied()));
+            if ordering.is_ne() {
+                return ordering;
+            }
+            left = left.get(1..).unwrap_or_default();
+            right = right.get(1..).unwrap_or_default();
+        }
+
+        let left_digits = left.iter().take_while(|ch| ch.is_ascii_digit()).count();
+        let right_digits = right.iter().take_while(|ch| ch.is_ascii_digit()).count();
+        let (left_number, rest) = left.split_at(left_digits);
+        left = rest;
+        let (right_number, rest) = right.split_at(right_digits);
+        right = rest;
+
+        // Compared as strings once leading zeroes are gone, so that they can't overflow
+        let left_number = trim_zeroes(left_number);
+        let right_number = trim_zeroes(right_number);
+        let ordering = left_number
+            .len()
+            .cmp(&right_number.len())
+            .then_with(|| left_number.cmp(right_number));
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+
+    Ordering::Equal
+}
+
+fn trim_zeroes(digits: &[u8]) -> &[u8] {
+    let zeroes = digits.iter().take_while(|ch| **ch == b'0').count();
+
+    &digits[zeroes..]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::{compare_debian_versions, compare_version_part, compare_versions};
+
+    #[test]
+    fn version_parts() {
+        assert_eq!(compare_version_part("1.10.0", "1.9.2"), Ordering::Greater);
+        assert_eq!(compare_version_part("1.0", "1.00"), Ordering::Equal);
+        assert_eq!(compare_version_part("1.0~rc1", "1.0"), Ordering::Less);
+        assert_eq!(compare_version_part("1.0~rc1", "1.0~rc2"), Ordering::Less);
+        assert_eq!(compare_version_part("1.0~~", "1.0~"), Ordering::Less);
+        assert_eq!(compare_version_part("1.0a", "1.0"), Ordering::Greater);
+        assert_eq!(compare_version_part("1.0a", "1.0+"), Ordering::Less);
+        assert_eq!(
+            compare_version_part("1.18446744073709551616", "1.18446744073709551615"),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn upstream_versions() {
+        assert_eq!(compare_versions("1.0-beta", "1.0"), Ordering::Less);
+        assert_eq!(compare_versions("1.0-beta", "1.0-rc1"), Ordering::Less);
+        assert_eq!(compare_versions("2.0", "1.10"), Ordering::Greater);
+    }
+
+    #[test]
+    fn debian_versions() {
+        assert_eq!(compare_debian_versions("1:1.0", "2.0"), Ordering::Greater);
+        assert_eq!(compare_debian_versions("0:2.0", "2.0"), Ordering::Equal);
+        assert_eq!(
+            compare_debian_versions("2.34-0ubuntu3", "2.34-0ubuntu3~1"),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare_debian_versions("3.0.2-0ubuntu1.10", "3.0.2-0ubuntu1.9"),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare_debian_versions("1.2-3-1", "1.2-3"),
+            Ordering::Greater
+        );
+    }
+}
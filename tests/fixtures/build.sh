@@ -0,0 +1,18 @@
+#!/bin/sh
+# Rebuild the shared objects the tests analyze, strip and split, from the sources next to them
+set -eu
+cd "$(dirname "$0")"
+
+# Debug info, a build-id, full RELRO, an NX stack, FORTIFY and a dependency on libm
+gcc -shared -fPIC -g -O2 -D_FORTIFY_SOURCE=2 -Wl,--build-id=sha1 -Wl,-z,relro,-z,now \
+    -o libhardened.so hardened.c -lm
+# Debug info, but a build-id too short to name its debug file after
+gcc -shared -fPIC -g -O2 -Wl,--build-id=0x01 -o libshortid.so hardened.c -lm
+# A PostgreSQL module linking against a library outside of libc
+gcc -shared -fPIC -O2 -o module.so module.c -lcrypt
+# Text relocations, no RELRO and an executable stack
+gcc -shared -fPIC -O2 -Wl,-z,notext -Wl,-z,norelro -Wl,-z,execstack \
+    -o libunhardened.so unhardened.c
+# PostgreSQL modules installing a planner hook, and one requesting shared memory as well
+gcc -shared -fPIC -O2 -o session_hook.so session_hook.c
+gcc -shared -fPIC -O2 -o shmem_hook.so shmem_hook.c
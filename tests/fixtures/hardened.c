@@ -0,0 +1,15 @@
+#include <math.h>
+#include <stdio.h>
+#include <string.h>
+
+int hardened_counter = 1;
+
+int hardened_format(char *out, double value)
+{
+    char buf[32];
+
+    sprintf(buf, "%.3f", sqrt(value));
+    strcpy(out, buf);
+
+    return hardened_counter++;
+}
@@ -0,0 +1,14 @@
+#include <crypt.h>
+
+/* What PG_MODULE_MAGIC expands to, give or take the contents */
+const void *Pg_magic_func(void)
+{
+    static const int magic_data = 160000;
+
+    return &magic_data;
+}
+
+char *module_hash(const char *password)
+{
+    return crypt(password, "$6$salt$");
+}
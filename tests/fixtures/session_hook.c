@@ -0,0 +1,8 @@
+extern void *planner_hook;
+
+void *previous_planner_hook;
+
+void _PG_init(void)
+{
+    previous_planner_hook = planner_hook;
+}
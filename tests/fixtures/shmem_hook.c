@@ -0,0 +1,10 @@
+extern void *planner_hook;
+extern void *shmem_request_hook;
+
+void *previous_hooks[2];
+
+void _PG_init(void)
+{
+    previous_hooks[0] = planner_hook;
+    previous_hooks[1] = shmem_request_hook;
+}
@@ -0,0 +1,12 @@
+int unhardened_counter = 1;
+
+/* The address of the counter, stored in the text segment */
+__asm__(".text\n"
+        ".globl unhardened_address\n"
+        "unhardened_address:\n"
+        ".quad unhardened_counter\n");
+
+int unhardened_next(void)
+{
+    return unhardened_counter++;
+}